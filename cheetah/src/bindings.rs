@@ -1,10 +1,16 @@
+use std::{error::Error, fmt::Display};
+
 use kuchiki::{Attribute, ExpandedName, NodeData};
 use kuchikikiki as kuchiki;
 use lazy_static::lazy_static;
-use locrian::{eval::EvalResult, parser::ExprValue};
+use locrian::{
+    eval::{EvalContext, EvalResult},
+    parser::{ExprValue, parse_expr},
+};
 use regex::{Captures, Regex};
+use serde_json::Value;
 
-use crate::template::TemplateContext;
+use crate::{functions, template::TemplateContext};
 
 pub struct BindingContext<'a> {
     component_name: Option<String>,
@@ -13,10 +19,91 @@ pub struct BindingContext<'a> {
 }
 
 lazy_static! {
-    pub static ref BIND_REGEX: Regex = Regex::new(r"[^\!]?\{\{(?P<var>.*?)\}\}").unwrap();
-    pub static ref LITERAL_BIND_REGEX: Regex = Regex::new(r"\!\{\{").unwrap();
+    pub static ref BIND_REGEX: Regex = Regex::new(r"(?P<escape>!)?\{\{(?P<var>.*?)\}\}").unwrap();
+}
+
+/// A binding whose expression couldn't be evaluated, or whose value can't be shown as text.
+#[derive(Debug)]
+pub struct BindingError {
+    /// The attribute being bound, or `None` for a `{{ }}` binding in text.
+    pub attr: Option<String>,
+    pub expr: String,
+    pub reason: String,
+}
+
+impl Error for BindingError {}
+
+impl Display for BindingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.attr {
+            Some(attr) => write!(
+                f,
+                "Could not bind [{attr}]=\"{}\": {}",
+                self.expr, self.reason
+            ),
+            None => write!(f, "Could not bind {{{{{}}}}}: {}", self.expr, self.reason),
+        }
+    }
+}
+
+/// Converts a JSON value (such as one from front matter) into a value for an expression.
+pub fn to_expr_value(value: &Value) -> ExprValue<'static> {
+    match value {
+        Value::Null => ExprValue::Null,
+        Value::Bool(b) => ExprValue::Boolean(*b),
+        Value::Number(n) => n.as_f64().map_or(ExprValue::Null, ExprValue::Number),
+        Value::String(s) => ExprValue::String(s.clone()),
+        Value::Array(a) => ExprValue::Array(a.iter().map(to_expr_value).collect()),
+        Value::Object(o) => ExprValue::Object(
+            o.iter()
+                .map(|(key, value)| (key.clone(), to_expr_value(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// Converts the result of an expression into a JSON value, so that it can be bound to a variable.
+pub fn to_json_value(result: &EvalResult) -> Value {
+    match result {
+        EvalResult::String(s) => Value::String(s.clone()),
+        EvalResult::Number(n) => {
+            serde_json::Number::from_f64(*n).map_or(Value::Null, Value::Number)
+        }
+        EvalResult::Boolean(b) => Value::Bool(*b),
+        EvalResult::Array(a) => Value::Array(a.iter().map(to_json_value).collect()),
+        EvalResult::Object(o) => Value::Object(
+            o.iter()
+                .map(|(key, value)| (key.clone(), to_json_value(value)))
+                .collect(),
+        ),
+        EvalResult::Quoted(..) | EvalResult::None => Value::Null,
+    }
+}
+
+/// Whether the result of an expression counts as true in an `<if>`: `false`, `null`, `0`, empty
+/// strings, and empty arrays are false, and everything else is true.
+pub fn is_truthy(result: &EvalResult) -> bool {
+    match result {
+        EvalResult::Boolean(b) => *b,
+        EvalResult::None => false,
+        EvalResult::Number(n) => *n != 0.0,
+        EvalResult::String(s) => !s.is_empty(),
+        EvalResult::Array(a) => !a.is_empty(),
+        EvalResult::Object(_) | EvalResult::Quoted(..) => true,
+    }
 }
 
+/// The string literals in an expression, which could be the names of packages passed to `use`.
+fn strings(expr: &ExprValue, found: &mut Vec<String>) {
+    match expr {
+        ExprValue::String(s) => found.push(s.clone()),
+        ExprValue::Array(items) => items.iter().for_each(|item| strings(item, found)),
+        ExprValue::Object(fields) => fields.iter().for_each(|(_, value)| strings(value, found)),
+        ExprValue::FnCall(_, args) => strings(args, found),
+        ExprValue::Quote(quoted) | ExprValue::QuoteWithCtx(quoted, _) => strings(quoted, found),
+        _ => (),
+    }
+}
 impl<'a> BindingContext<'a> {
     pub fn new(
         component_name: Option<String>,
@@ -30,70 +117,275 @@ impl<'a> BindingContext<'a> {
         }
     }
 
-    pub fn locrian_ctx(&self) -> locrian::eval::EvalContext<'a> {
-        let mut ctx = locrian::stdlib::STDLIB.with_var(
-            "$me",
-            self.component_name
-                .clone()
-                .map(ExprValue::String)
-                .unwrap_or(ExprValue::Null),
-        );
-        for (key, value) in &self.ctx.attrs {
-            ctx.vars.insert(
-                key.local.to_string(),
-                ExprValue::String(value.value.clone()),
+    /// Looks up a variable for an expression: variables (like props and loop variables) first,
+    /// then the fields of objects as `name:field` (since expressions have no other way to reach
+    /// them), then attributes.
+    fn lookup(&self, name: &str) -> Option<ExprValue<'static>> {
+        if name == "$me" {
+            return Some(
+                self.component_name
+                    .clone()
+                    .map_or(ExprValue::Null, ExprValue::String),
             );
         }
-        ctx
+        let field = || {
+            name.match_indices(':').find_map(|(i, _)| {
+                let mut value = self.ctx.vars.get(&name[..i])?;
+                for field in name[i + 1..].split(':') {
+                    value = value.as_object()?.get(field)?;
+                }
+                Some(value)
+            })
+        };
+        if let Some(value) = self.ctx.vars.get(name).or_else(field) {
+            return Some(to_expr_value(value));
+        }
+        self.ctx
+            .attrs
+            .iter()
+            .find(|(key, _)| &*key.local == name)
+            .map(|(_, value)| ExprValue::String(value.value.clone()))
+    }
+
+    /// Puts the value of each variable that the expression uses in place of its name. Evaluating
+    /// an expression copies its context over and over (once for every item of an array, for
+    /// one), so it's kept to the functions and whatever quoted code needs, which can't be
+    /// filled in until it's called.
+    fn bind<'e>(
+        &self,
+        expr: ExprValue<'e>,
+        ctx: &mut EvalContext<'e>,
+        packages: &[String],
+        quoted: bool,
+    ) -> ExprValue<'e> {
+        // Names in quoted code can also be in one of the packages passed to `use`.
+        let add_to_ctx = |name: &str, ctx: &mut EvalContext<'e>| {
+            let names = std::iter::once(name.to_string())
+                .chain(packages.iter().map(|package| format!("{package}:{name}")));
+            for name in names {
+                if let Some(value) = self.lookup(&name) {
+                    ctx.vars.insert(name, value);
+                }
+            }
+        };
+        match expr {
+            ExprValue::Ident(name) if !quoted => self.lookup(name).unwrap_or(expr),
+            ExprValue::Ident(name) => {
+                add_to_ctx(name, ctx);
+                expr
+            }
+            ExprValue::FnCall(name, args) => {
+                // A variable can hold quoted code to call.
+                add_to_ctx(name, ctx);
+                ExprValue::FnCall(name, Box::new(self.bind(*args, ctx, packages, quoted)))
+            }
+            ExprValue::Array(items) => ExprValue::Array(
+                items
+                    .into_iter()
+                    .map(|item| self.bind(item, ctx, packages, quoted))
+                    .collect(),
+            ),
+            ExprValue::Object(fields) => ExprValue::Object(
+                fields
+                    .into_iter()
+                    .map(|(key, value)| (key, self.bind(value, ctx, packages, quoted)))
+                    .collect(),
+            ),
+            ExprValue::Quote(code) => {
+                ExprValue::Quote(Box::new(self.bind(*code, ctx, packages, true)))
+            }
+            expr => expr,
+        }
+    }
+
+    /// Evaluates an expression, failing if any function it calls does.
+    pub fn eval<'e>(&self, expr: &'e str) -> Result<EvalResult<'e>, Box<dyn Error>> {
+        let expr = parse_expr(expr)?;
+        let mut packages = vec![];
+        strings(&expr, &mut packages);
+        let mut ctx = functions::context();
+        let expr = self.bind(expr, &mut ctx, &packages, false);
+        // A failure left over from an expression that failed to evaluate for another reason
+        // isn't this one's.
+        functions::take_error();
+        let result = locrian::eval::eval(expr, ctx)?;
+        match functions::take_error() {
+            Some(reason) => Err(reason.into()),
+            None => Ok(result),
+        }
     }
 
-    pub fn expand_attributes(&self) {
+    /// Evaluates each `[attr]="expression"` attribute into a plain `attr` attribute, returning
+    /// the expressions that failed (which are bound as empty strings).
+    pub fn expand_attributes(&self) -> Vec<BindingError> {
+        let mut failures = vec![];
         if let NodeData::Element(element) = self.node {
             let mut attrs = element.attributes.borrow_mut();
             for (name, value) in attrs.map.clone() {
-                if name.local.starts_with('[') && name.local.ends_with(']') {
+                if let Some(attr) = name
+                    .local
+                    .strip_prefix('[')
+                    .and_then(|name| name.strip_suffix(']'))
+                {
+                    let value = self.eval_to_string(&value.value).unwrap_or_else(|reason| {
+                        failures.push(BindingError {
+                            attr: Some(attr.to_string()),
+                            expr: value.value.clone(),
+                            reason,
+                        });
+                        String::new()
+                    });
                     attrs.map.insert(
-                        ExpandedName::new(
-                            "",
-                            name.local
-                                .clone()
-                                .strip_prefix('[')
-                                .unwrap()
-                                .strip_suffix(']')
-                                .unwrap(),
-                        ),
+                        ExpandedName::new("", attr),
                         Attribute {
                             prefix: None,
-                            value: if let Ok(EvalResult::String(s)) =
-                                locrian::eval_str(&value.value, self.locrian_ctx())
-                            {
-                                s
-                            } else {
-                                "".to_string()
-                            },
+                            value,
                         },
                     );
                 }
             }
         }
+        failures
     }
 
-    pub fn expand_text(&self) {
+    /// Replaces each `{{expression}}` in a text node with its value, and each `!{{...}}` with
+    /// `{{...}}`, returning the expressions that failed (which are replaced with empty strings).
+    pub fn expand_text(&self) -> Vec<BindingError> {
+        let mut failures = vec![];
         if let NodeData::Text(text_ref) = self.node {
             let mut text = text_ref.borrow_mut();
             *text = BIND_REGEX
                 .replace_all(&text, |caps: &Captures| {
-                    if let Some(expr) = caps.get(1)
-                        && let Ok(EvalResult::String(s)) =
-                            locrian::eval_str(expr.as_str(), self.locrian_ctx())
-                    {
-                        s
-                    } else {
-                        "".to_string()
+                    let expr = &caps["var"];
+                    if caps.name("escape").is_some() {
+                        return format!("{{{{{expr}}}}}");
                     }
+                    self.eval_to_string(expr).unwrap_or_else(|reason| {
+                        failures.push(BindingError {
+                            attr: None,
+                            expr: expr.to_string(),
+                            reason,
+                        });
+                        String::new()
+                    })
                 })
                 .to_string();
-            *text = LITERAL_BIND_REGEX.replace_all(&text, "{{").to_string();
         }
+        failures
+    }
+
+    /// Evaluates an expression into text: strings as they are, numbers and booleans as they'd be
+    /// written, and `null` as nothing. Anything else is an error.
+    fn eval_to_string(&self, expr: &str) -> Result<String, String> {
+        match self.eval(expr) {
+            Ok(EvalResult::String(s)) => Ok(s),
+            Ok(EvalResult::Number(n)) => Ok(n.to_string()),
+            Ok(EvalResult::Boolean(b)) => Ok(b.to_string()),
+            Ok(EvalResult::None) => Ok(String::new()),
+            Ok(other) => Err(format!(
+                "evaluated to {other}, which can't be shown as text"
+            )),
+            Err(e) => match e.downcast_ref::<pest::error::Error<locrian::parser::Rule>>() {
+                Some(e) => Err(format!("invalid expression, {}", e.variant.message())),
+                None => Err(e.to_string()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use kuchiki::NodeRef;
+    use locrian::eval::NoSuchIdentError;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        template::{Scope, TemplateContext},
+        testing::context,
+    };
+
+    fn ctx(vars: Value, attrs: &[(&str, &str)]) -> TemplateContext {
+        let Value::Object(vars) = vars else {
+            panic!("vars must be an object");
+        };
+        TemplateContext {
+            vars: Scope::new(vars),
+            attrs: attrs
+                .iter()
+                .map(|(name, value)| {
+                    (
+                        ExpandedName::new("", *name),
+                        Attribute {
+                            prefix: None,
+                            value: value.to_string(),
+                        },
+                    )
+                })
+                .collect(),
+            component_name: Some("my-card".into()),
+            ..context(Path::new(""))
+        }
+    }
+
+    fn eval(ctx: &TemplateContext, expr: &str) -> Result<String, Box<dyn Error>> {
+        let node = NodeRef::new_text("");
+        let binding = BindingContext::new(ctx.component_name.clone(), node.data(), ctx);
+        Ok(binding.eval(expr)?.to_string())
+    }
+
+    #[test]
+    fn variables_attributes_and_me() {
+        let ctx = ctx(
+            json!({ "title": "Var" }),
+            &[("title", "Attr"), ("alt", "A")],
+        );
+        assert_eq!(eval(&ctx, "title").unwrap(), "Var");
+        assert_eq!(eval(&ctx, "alt").unwrap(), "A");
+        assert_eq!(eval(&ctx, "$me").unwrap(), "my-card");
+    }
+
+    #[test]
+    fn fields_of_objects() {
+        let ctx = ctx(
+            json!({ "author": { "name": "Ann", "site": { "url": "/" } } }),
+            &[],
+        );
+        assert_eq!(eval(&ctx, "author:name").unwrap(), "Ann");
+        assert_eq!(eval(&ctx, "author:site:url").unwrap(), "/");
+        assert_eq!(
+            eval(&ctx, r#"use(["author"], 'name)"#).unwrap(),
+            "Ann",
+            "names in quoted code can be in a package"
+        );
+    }
+
+    #[test]
+    fn variables_in_arguments_and_quoted_code() {
+        let ctx = ctx(json!({ "tags": ["a", "b"], "suffix": "!" }), &[]);
+        assert_eq!(eval(&ctx, "array:length(tags)").unwrap(), "2");
+        assert_eq!(
+            eval(&ctx, r#"array:map(tags, 'string:concat($it, suffix))"#).unwrap(),
+            "[a!, b!]"
+        );
+    }
+
+    #[test]
+    fn undefined_variables() {
+        let error = eval(&ctx(json!({}), &[]), "missing").unwrap_err();
+        assert!(error.is::<NoSuchIdentError>());
+    }
+
+    #[test]
+    fn text_bindings() {
+        let ctx = ctx(json!({ "n": 2 }), &[]);
+        let node = NodeRef::new_text("{{ n }} !{{ n }} {{ nope }}");
+        let binding = BindingContext::new(None, node.data(), &ctx);
+        let failures = binding.expand_text();
+        assert_eq!(node.text_contents(), "2 {{ n }} ");
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].expr, " nope ");
     }
 }
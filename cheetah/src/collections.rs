@@ -0,0 +1,253 @@
+use std::{error::Error, fs, path::PathBuf};
+
+use lazy_static::lazy_static;
+use rayon::prelude::*;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    config::Settings, error::BuildError, frontmatter::FrontMatter, template::TemplateLoader,
+};
+
+const EXCERPT_LENGTH: usize = 200;
+
+lazy_static! {
+    static ref DATE_PREFIX_REGEX: Regex = Regex::new(r"^\d{4}-\d{2}-\d{2}").unwrap();
+}
+
+/// Everything that templates can know about a page without rendering it.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PageInfo {
+    pub path: PathBuf,
+    pub url: String,
+    pub front_matter: FrontMatter,
+    pub date: Option<String>,
+    pub heading: Option<String>,
+    pub excerpt: String,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct PageIndex {
+    pub pages: Vec<PageInfo>,
+}
+
+impl PageInfo {
+    fn new(
+        path: PathBuf,
+        loader: &TemplateLoader,
+        settings: &Settings,
+    ) -> Result<Self, Box<dyn Error>> {
+        let template = loader.load(&path)?;
+        let relative = path.strip_prefix(&settings.pages_dir).unwrap_or(&path);
+        let url = format!(
+            "/{}",
+            relative
+                .with_extension("html")
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/")
+        );
+
+        let date = match template.front_matter.get("date") {
+            Some(Value::String(date)) => Some(date.clone()),
+            _ => path.file_name().and_then(|name| {
+                DATE_PREFIX_REGEX
+                    .find(&name.to_string_lossy())
+                    .map(|date| date.as_str().to_string())
+            }),
+        };
+        let heading = template
+            .dom
+            .select_first("h1, h2, h3, h4, h5, h6")
+            .ok()
+            .map(|heading| heading.text_contents().trim().to_string());
+        let excerpt = match template.front_matter.get("excerpt") {
+            Some(Value::String(excerpt)) => excerpt.clone(),
+            _ => template
+                .dom
+                .select_first("p")
+                .map(|p| truncate(p.text_contents().trim(), EXCERPT_LENGTH))
+                .unwrap_or_default(),
+        };
+
+        Ok(Self {
+            path,
+            url,
+            front_matter: template.front_matter,
+            date,
+            heading,
+            excerpt,
+        })
+    }
+
+    /// The value bound to the loop variable of a `<collection>`. Front matter takes precedence
+    /// over the computed fields, and `title` falls back to the first heading.
+    pub fn to_value(&self) -> Value {
+        let mut value = serde_json::Map::new();
+        value.insert("path".into(), self.path.to_string_lossy().into());
+        value.insert("url".into(), self.url.clone().into());
+        value.insert("date".into(), self.date.clone().into());
+        value.insert("title".into(), self.heading.clone().into());
+        value.insert("heading".into(), self.heading.clone().into());
+        value.insert("excerpt".into(), self.excerpt.clone().into());
+        value.extend(self.front_matter.clone());
+        Value::Object(value)
+    }
+
+    fn sort_key(&self, key: &str) -> String {
+        match self.to_value().get(key) {
+            Some(Value::String(s)) => s.clone(),
+            Some(Value::Null) | None => String::new(),
+            Some(other) => other.to_string(),
+        }
+    }
+}
+
+/// Finds every page under `dir`, relative to the site root.
+fn find_recursive(
+    dir: PathBuf,
+    loader: &TemplateLoader,
+    paths: &mut Vec<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    if let Ok(pages) = fs::read_dir(loader.resolve(&dir)) {
+        for entry in pages.flatten() {
+            let full_path = dir.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                find_recursive(full_path, loader, paths)?;
+            } else {
+                paths.push(full_path);
+            }
+        }
+    }
+    Ok(())
+}
+
+impl PageIndex {
+    /// Reads the front matter and contents of every page, without rendering anything. Pages are
+    /// read in parallel, which also leaves each of them in the template cache of a worker thread.
+    pub fn build(loader: &TemplateLoader, settings: &Settings) -> Result<Self, Box<dyn Error>> {
+        let mut paths = vec![];
+        find_recursive(settings.pages_dir.clone(), loader, &mut paths)?;
+        let mut pages = paths
+            .into_par_iter()
+            .map(|path| PageInfo::new(path, loader, settings).map_err(BuildError::new))
+            .collect::<Result<Vec<_>, _>>()?;
+        pages.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(Self { pages })
+    }
+
+    /// The pages under `dir` (a URL path such as `/blog`), excluding the directory's own index
+    /// page, sorted by `sort` (a field of [`PageInfo::to_value`]).
+    pub fn query(&self, dir: &str, sort: &str, descending: bool) -> Vec<&PageInfo> {
+        let prefix = format!("/{}/", dir.trim_matches('/')).replace("//", "/");
+        let index_page = format!("{prefix}index.html");
+        let mut pages = self
+            .pages
+            .iter()
+            .filter(|page| page.url.starts_with(&prefix) && page.url != index_page)
+            .collect::<Vec<_>>();
+        pages.sort_by_cached_key(|page| page.sort_key(sort));
+        if descending {
+            pages.reverse();
+        }
+        pages
+    }
+}
+
+fn truncate(text: &str, length: usize) -> String {
+    if text.chars().count() <= length {
+        return text.to_string();
+    }
+    let truncated = text.chars().take(length).collect::<String>();
+    match truncated.rfind(char::is_whitespace) {
+        Some(end) => format!("{}…", truncated[..end].trim_end()),
+        None => format!("{truncated}…"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        template::{Template, TemplateContext},
+        testing::{TempDir, context, settings},
+    };
+
+    fn blog() -> TempDir {
+        let dir = TempDir::new();
+        dir.write("pages/about.html", "<h1>About</h1>");
+        dir.write("pages/blog/index.html", "<h1>Blog</h1>");
+        dir.write("pages/blog/2024-03-01-spring.md", "# Spring\n\nFlowers.");
+        dir.write(
+            "pages/blog/winter.html",
+            "+++\ndate = \"2024-01-15\"\ntitle = \"Snow\"\n+++\n<h1>Winter</h1>",
+        );
+        dir.write("pages/blog/old/2023-06-01-summer.md", "# Summer");
+        dir
+    }
+
+    fn index(dir: &TempDir) -> PageIndex {
+        PageIndex::build(&context(dir.path()).loader, &settings(dir.path())).unwrap()
+    }
+
+    fn urls(pages: Vec<&PageInfo>) -> Vec<&str> {
+        pages.iter().map(|page| page.url.as_str()).collect()
+    }
+
+    #[test]
+    fn query_lists_a_directory_without_its_index() {
+        let dir = blog();
+        let index = index(&dir);
+        assert_eq!(
+            urls(index.query("/blog", "url", false)),
+            [
+                "/blog/2024-03-01-spring.html",
+                "/blog/old/2023-06-01-summer.html",
+                "/blog/winter.html",
+            ]
+        );
+        assert_eq!(index.query("/", "url", false).len(), 5);
+        assert!(index.query("/nothing", "url", false).is_empty());
+    }
+
+    #[test]
+    fn query_sorts_by_any_field() {
+        let dir = blog();
+        let index = index(&dir);
+        assert_eq!(
+            urls(index.query("/blog", "date", true)),
+            [
+                "/blog/2024-03-01-spring.html",
+                "/blog/winter.html",
+                "/blog/old/2023-06-01-summer.html",
+            ]
+        );
+        let titles = index
+            .query("/blog", "title", false)
+            .iter()
+            .map(|page| page.to_value()["title"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, ["Snow", "Spring", "Summer"]);
+    }
+
+    #[test]
+    fn collection_element() {
+        let dir = blog();
+        let ctx = TemplateContext {
+            pages: Arc::new(index(&dir)),
+            ..context(dir.path())
+        };
+        let template = Template::from_html(
+            r#"<collection dir="/blog" as="post" sort="date" order="desc" limit="2">{{ post:title }};</collection>"#
+                .to_string(),
+        )
+        .unwrap();
+        let (root, _) = template.render(&ctx).unwrap();
+        assert_eq!(root.text_contents(), "Spring;Snow;");
+        assert!(ctx.dependencies.borrow().pages);
+    }
+}
@@ -1,31 +1,130 @@
-use std::sync::Mutex;
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
 
 use config::{Config, ConfigError, Environment, File, FileFormat};
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
 
-use crate::hooks::Hook;
+use crate::{hooks::Hook, wasm::PluginConfig};
 
 lazy_static! {
+    pub static ref OVERRIDES: Mutex<Overrides> = Mutex::new(Overrides::default());
     pub static ref SETTINGS: Mutex<Settings> = Mutex::new(Settings::new().unwrap());
 }
 
+/// Settings given on the command line, which take precedence over `cheetah.toml`.
+#[derive(Clone, Debug, Default)]
+pub struct Overrides {
+    pub root: Option<PathBuf>,
+    pub config: Option<PathBuf>,
+    pub pages_dir: Option<PathBuf>,
+    pub components_dir: Option<PathBuf>,
+    pub layouts_dir: Option<PathBuf>,
+    pub assets_dir: Option<PathBuf>,
+    pub out_dir: Option<PathBuf>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    /// Whether the site is being served by the development server, which changes some defaults.
+    pub dev: bool,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Settings {
     pub always_hydrate: bool,
+    /// Whether a binding that fails fails the build, rather than being left empty with a warning.
+    pub strict: bool,
+    pub root: PathBuf,
+    pub pages_dir: PathBuf,
+    pub components_dir: PathBuf,
+    pub layouts_dir: PathBuf,
+    pub assets_dir: PathBuf,
+    pub out_dir: PathBuf,
+    /// The address that `cheetah dev` and `cheetah serve` listen on. If the port is taken, the next
+    /// free one is used instead.
+    pub host: String,
+    pub port: u16,
+    /// Whether the server resolves URLs without an extension, like `/about`, to `.html` files.
+    pub clean_urls: bool,
+    /// How many hooks can run at once, when they don't depend on each other.
+    pub hook_concurrency: usize,
     pub hooks: Vec<Hook>,
+    /// WebAssembly plugins, which run in the order they're listed.
+    pub plugins: Vec<PluginConfig>,
 }
 
 impl Settings {
     pub fn new() -> Result<Self, ConfigError> {
-        Config::builder()
+        let overrides = OVERRIDES.lock().unwrap().clone();
+        let config_file = match &overrides.config {
+            Some(path) => File::from(path.as_path()).required(true),
+            None => File::from(config_file(&overrides)).required(false),
+        };
+        let mut builder = Config::builder()
+            .set_default("strict", !overrides.dev)?
             .add_source(File::from_str(
                 include_str!("config.default.toml"),
                 FileFormat::Toml,
             ))
-            .add_source(File::with_name("cheetah.toml").required(false))
-            .add_source(Environment::with_prefix("CHEETAH"))
+            .add_source(config_file)
+            .add_source(Environment::with_prefix("CHEETAH"));
+        for (key, value) in [
+            ("root", &overrides.root),
+            ("pages_dir", &overrides.pages_dir),
+            ("components_dir", &overrides.components_dir),
+            ("layouts_dir", &overrides.layouts_dir),
+            ("assets_dir", &overrides.assets_dir),
+            ("out_dir", &overrides.out_dir),
+        ] {
+            builder = builder.set_override_option(
+                key,
+                value
+                    .as_ref()
+                    .map(|path| path.to_string_lossy().to_string()),
+            )?;
+        }
+        builder
+            .set_override_option("host", overrides.host)?
+            .set_override_option("port", overrides.port.map(u64::from))?
             .build()?
             .try_deserialize()
     }
+
+    /// Resolves a path relative to the root of the site.
+    pub fn resolve(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.root.join(path)
+    }
+
+    /// The configuration file in use, whether or not it exists.
+    pub fn config_file(&self) -> PathBuf {
+        config_file(&OVERRIDES.lock().unwrap())
+    }
+
+    /// The directory that the rendered site is written to and served from.
+    pub fn pages_out(&self) -> PathBuf {
+        self.resolve(&self.out_dir).join("pages")
+    }
+
+    /// The output path for a page, given its path relative to the site root.
+    pub fn page_out(&self, page: &Path) -> PathBuf {
+        self.pages_out()
+            .join(page.strip_prefix(&self.pages_dir).unwrap_or(page))
+    }
+
+    /// If the absolute path `path` is inside `dir`, returns it relative to the site root.
+    pub fn within(&self, path: &Path, dir: &Path) -> Option<PathBuf> {
+        let dir_path = std::path::absolute(self.resolve(dir)).ok()?;
+        path.strip_prefix(dir_path).ok().map(|rest| dir.join(rest))
+    }
+}
+
+fn config_file(overrides: &Overrides) -> PathBuf {
+    overrides.config.clone().unwrap_or_else(|| {
+        overrides
+            .root
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("cheetah.toml")
+    })
 }
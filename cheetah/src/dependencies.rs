@@ -0,0 +1,154 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// What a page was built from, besides its own source.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Dependencies {
+    /// The layouts and components used, relative to the site root.
+    pub templates: BTreeSet<PathBuf>,
+    /// Whether the page lists other pages (with `<collection>`), so it depends on all of them.
+    pub pages: bool,
+}
+
+/// What each page was built from, so that changing a layout or component only rebuilds the pages
+/// that use it.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct DependencyGraph {
+    pages: BTreeMap<PathBuf, Dependencies>,
+}
+
+impl DependencyGraph {
+    /// Records what `page` was last built from.
+    pub fn insert(&mut self, page: PathBuf, dependencies: Dependencies) {
+        self.pages.insert(page, dependencies);
+    }
+
+    pub fn get(&self, page: &Path) -> Option<&Dependencies> {
+        self.pages.get(page)
+    }
+
+    /// Forgets `page`, or every page under it if it's a directory.
+    pub fn remove(&mut self, page: &Path) {
+        self.pages.retain(|path, _| !path.starts_with(page));
+    }
+
+    /// The pages that were built from `template` (or from any template under it, if it's a
+    /// directory), directly or through other templates.
+    pub fn dependents(&self, template: &Path) -> Vec<PathBuf> {
+        self.pages
+            .iter()
+            .filter(|(_, dependencies)| {
+                dependencies
+                    .templates
+                    .iter()
+                    .any(|path| path.starts_with(template))
+            })
+            .map(|(page, _)| page.clone())
+            .collect()
+    }
+
+    /// The pages that list other pages, and so have to be rebuilt when any page changes.
+    pub fn listing_pages(&self) -> Vec<PathBuf> {
+        self.pages
+            .iter()
+            .filter(|(_, dependencies)| dependencies.pages)
+            .map(|(page, _)| page.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        template::Template,
+        testing::{TempDir, context},
+    };
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    fn graph() -> DependencyGraph {
+        let mut graph = DependencyGraph::default();
+        for (page, templates, pages) in [
+            (
+                "pages/a.html",
+                &["layouts/base.html", "components/x-card.html"][..],
+                false,
+            ),
+            ("pages/b.html", &["layouts/base.html"][..], true),
+            ("pages/blog/c.html", &["components/x-list.html"][..], false),
+        ] {
+            graph.insert(
+                page.into(),
+                Dependencies {
+                    templates: templates.iter().map(PathBuf::from).collect(),
+                    pages,
+                },
+            );
+        }
+        graph
+    }
+
+    #[test]
+    fn dependents_of_a_template() {
+        let graph = graph();
+        assert_eq!(
+            graph.dependents(Path::new("layouts/base.html")),
+            paths(&["pages/a.html", "pages/b.html"])
+        );
+        assert_eq!(
+            graph.dependents(Path::new("components/x-list.html")),
+            paths(&["pages/blog/c.html"])
+        );
+        assert!(
+            graph
+                .dependents(Path::new("components/x-other.html"))
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn dependents_of_a_directory() {
+        assert_eq!(
+            graph().dependents(Path::new("components")),
+            paths(&["pages/a.html", "pages/blog/c.html"])
+        );
+    }
+
+    #[test]
+    fn removing_and_listing_pages() {
+        let mut graph = graph();
+        assert_eq!(graph.listing_pages(), paths(&["pages/b.html"]));
+        graph.remove(Path::new("pages/blog"));
+        assert!(graph.get(Path::new("pages/blog/c.html")).is_none());
+        assert!(
+            graph
+                .dependents(Path::new("components/x-list.html"))
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn nested_components_are_dependencies() {
+        let dir = TempDir::new();
+        dir.write("components/x-outer.html", "<x-inner></x-inner>");
+        dir.write("components/x-inner.html", "<p>Inner</p>");
+        let ctx = context(dir.path());
+        Template::from_html("<x-outer></x-outer>".into())
+            .unwrap()
+            .render(&ctx)
+            .unwrap();
+        let mut graph = DependencyGraph::default();
+        graph.insert("pages/index.html".into(), ctx.dependencies.take());
+        assert_eq!(
+            graph.dependents(Path::new("components/x-inner.html")),
+            paths(&["pages/index.html"])
+        );
+    }
+}
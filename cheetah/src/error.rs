@@ -0,0 +1,216 @@
+use std::{borrow::Cow, error::Error, fmt::Display, path::PathBuf, sync::Arc};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref ANSI_REGEX: Regex = Regex::new("\x1b\\[[0-9;]*m").unwrap();
+}
+
+/// Removes the colors from an error message, to show it somewhere other than a terminal.
+pub fn without_colors(message: &str) -> Cow<'_, str> {
+    ANSI_REGEX.replace_all(message, "")
+}
+
+/// A range of bytes in a source file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A place in a template that an error can point to. Without a span, it refers to the whole file.
+#[derive(Clone, Debug)]
+pub struct Location {
+    pub path: PathBuf,
+    pub source: Arc<str>,
+    pub span: Option<Span>,
+}
+
+/// How the template an error happened in was reached from the page being built.
+#[derive(Clone, Debug)]
+pub enum Via {
+    /// The template is a component, used at the given location.
+    Component(String, Location),
+    /// The template is a layout, extended at the given location.
+    Extends(PathBuf, Location),
+}
+
+/// An error in building a page, with where in its templates it happened. Only the message of the
+/// original error is kept, so that it can be sent between threads.
+#[derive(Debug, Clone)]
+pub struct BuildError {
+    pub message: String,
+    pub location: Option<Location>,
+    /// The components and layouts between the page and the template the error happened in,
+    /// innermost first.
+    pub chain: Vec<Via>,
+}
+
+impl Location {
+    /// The 1-based line and column (in characters) that the location starts at.
+    pub fn line_col(&self) -> Option<(usize, usize)> {
+        let start = self.span?.start.min(self.source.len());
+        let before = &self.source[..start];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Some((
+            before.matches('\n').count() + 1,
+            before[line_start..].chars().count() + 1,
+        ))
+    }
+
+    /// The line that the location starts on, and the range of it that the span covers.
+    fn line(&self) -> Option<(&str, usize, usize)> {
+        let span = self.span?;
+        let start = span.start.min(self.source.len());
+        let line_start = self.source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = self.source[start..]
+            .find('\n')
+            .map_or(self.source.len(), |i| start + i);
+        let line = self.source[line_start..line_end].trim_end_matches('\r');
+        let from = self.source[line_start..start].chars().count();
+        let to = self.source[line_start..span.end.clamp(start, line_start + line.len())]
+            .chars()
+            .count();
+        Some((line, from, to.max(from + 1)))
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line_col() {
+            Some((line, col)) => write!(f, "{}:{line}:{col}", self.path.display()),
+            None => write!(f, "{}", self.path.display()),
+        }
+    }
+}
+
+impl Display for Via {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Via::Component(name, used_at) => write!(f, "in <{name}>, used at {used_at}"),
+            Via::Extends(path, extended_at) => {
+                write!(f, "in {}, extended at {extended_at}", path.display())
+            }
+        }
+    }
+}
+
+impl BuildError {
+    pub fn new(error: impl Into<Box<dyn Error>>) -> Self {
+        let error = error.into();
+        match error.downcast::<BuildError>() {
+            Ok(e) => *e,
+            Err(error) => Self {
+                message: error.to_string(),
+                location: None,
+                chain: vec![],
+            },
+        }
+    }
+
+    /// Points an error at `location`, unless it already points somewhere more specific.
+    pub fn at(error: impl Into<Box<dyn Error>>, location: Location) -> Self {
+        let mut e = Self::new(error);
+        if e.location.is_none() {
+            e.location = Some(location);
+        }
+        e
+    }
+
+    /// Records that an error happened inside a component or layout. An error that doesn't point
+    /// anywhere yet (like a missing file) is pointed at where the template was used instead.
+    pub fn within(error: impl Into<Box<dyn Error>>, via: Via) -> Self {
+        let mut e = Self::new(error);
+        if e.location.is_none() {
+            e.location = Some(match &via {
+                Via::Component(_, location) | Via::Extends(_, location) => location.clone(),
+            });
+        } else {
+            e.chain.push(via);
+        }
+        e
+    }
+}
+
+impl Error for BuildError {}
+
+/// Shows the message, followed by a code frame pointing at where the error happened.
+impl Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+        let Some(location) = &self.location else {
+            return Ok(());
+        };
+        let gutter = location
+            .line_col()
+            .map_or(0, |(line, _)| line.to_string().len());
+        let pad = " ".repeat(gutter);
+        write!(f, "\n{pad}\x1b[1;34m-->\x1b[0m {location}")?;
+        if let (Some((line, from, to)), Some((number, _))) = (location.line(), location.line_col())
+        {
+            write!(f, "\n{pad} \x1b[1;34m|\x1b[0m")?;
+            write!(f, "\n\x1b[1;34m{number} |\x1b[0m {line}")?;
+            // Keep tabs so that the carets line up with the line above.
+            let indent = line
+                .chars()
+                .take(from)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect::<String>();
+            write!(
+                f,
+                "\n{pad} \x1b[1;34m|\x1b[0m {indent}\x1b[1;31m{}\x1b[0m",
+                "^".repeat(to - from)
+            )?;
+        }
+        for via in &self.chain {
+            write!(f, "\n{pad} \x1b[1;34m=\x1b[0m note: {via}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(source: &str, start: usize, end: usize) -> Location {
+        Location {
+            path: PathBuf::from("pages/index.html"),
+            source: source.into(),
+            span: Some(Span { start, end }),
+        }
+    }
+
+    #[test]
+    fn line_col() {
+        let source = "<h1>Hi</h1>\n<p>{{ x }}</p>";
+        assert_eq!(at(source, 0, 1).line_col(), Some((1, 1)));
+        assert_eq!(at(source, 15, 22).line_col(), Some((2, 4)));
+        assert_eq!(at(source, 12, 13).line_col(), Some((2, 1)));
+    }
+
+    #[test]
+    fn line_col_counts_characters() {
+        let source = "<p>héllo {{ x }}</p>";
+        let start = source.find("{{").unwrap();
+        assert_eq!(at(source, start, start + 7).line_col(), Some((1, 10)));
+    }
+
+    #[test]
+    fn line_col_past_the_end() {
+        assert_eq!(at("a\nb", 10, 11).line_col(), Some((2, 2)));
+        let whole_file = Location {
+            span: None,
+            ..at("a", 0, 0)
+        };
+        assert_eq!(whole_file.line_col(), None);
+        assert_eq!(whole_file.to_string(), "pages/index.html");
+    }
+
+    #[test]
+    fn line_without_carriage_return() {
+        let source = "a\r\n  <x-y>\r\n";
+        assert_eq!(at(source, 5, 10).line(), Some(("  <x-y>", 2, 7)));
+    }
+}
@@ -0,0 +1,236 @@
+use std::{error::Error, fmt::Display};
+
+use serde_json::{Map, Number, Value};
+use yaml_rust2::{Yaml, YamlLoader};
+
+use crate::error::Span;
+
+pub type FrontMatter = Map<String, Value>;
+
+/// The `usize`s are byte offsets into the file, so errors can point at where they are: the
+/// opening fence for an unterminated block, the start of the TOML, or the YAML error itself.
+#[derive(Debug)]
+pub enum FrontMatterError {
+    Unterminated(&'static str, usize),
+    NotATable,
+    Toml(toml::de::Error, usize),
+    Yaml(yaml_rust2::ScanError, usize),
+}
+
+impl FrontMatterError {
+    /// Where in the file the error is.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            FrontMatterError::Unterminated(fence, start) => Some(Span {
+                start: *start,
+                end: start + fence.len(),
+            }),
+            FrontMatterError::NotATable => None,
+            FrontMatterError::Toml(e, start) => e.span().map(|span| Span {
+                start: start + span.start,
+                end: start + span.end,
+            }),
+            FrontMatterError::Yaml(_, index) => Some(Span {
+                start: *index,
+                end: index + 1,
+            }),
+        }
+    }
+}
+
+impl Error for FrontMatterError {}
+
+impl Display for FrontMatterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrontMatterError::Unterminated(fence, _) => {
+                write!(f, "Front matter is missing its closing {fence}")
+            }
+            FrontMatterError::NotATable => write!(f, "Front matter must be a table of keys"),
+            FrontMatterError::Toml(e, _) => {
+                write!(f, "Invalid TOML front matter: {}", e.message().trim())
+            }
+            FrontMatterError::Yaml(e, _) => write!(f, "Invalid YAML front matter: {}", e.info()),
+        }
+    }
+}
+
+/// Splits `+++`-fenced TOML or `---`-fenced YAML front matter off the start of `source`,
+/// returning it along with the rest of the source.
+pub fn extract(source: &str) -> Result<(FrontMatter, &str), FrontMatterError> {
+    let bom = source.len();
+    let source = source.strip_prefix('\u{feff}').unwrap_or(source);
+    let bom = bom - source.len();
+    let Some(fence) = ["+++", "---"]
+        .into_iter()
+        .find(|fence| first_line(source) == *fence)
+    else {
+        return Ok((FrontMatter::new(), source));
+    };
+
+    let mut offset = source.find('\n').map_or(source.len(), |i| i + 1);
+    let start = offset;
+    loop {
+        if offset >= source.len() {
+            return Err(FrontMatterError::Unterminated(fence, bom));
+        }
+        let line = first_line(&source[offset..]);
+        let end = offset;
+        offset = source[offset..]
+            .find('\n')
+            .map_or(source.len(), |i| offset + i + 1);
+        if line == fence {
+            let front_matter = &source[start..end];
+            let rest = &source[offset..];
+            return Ok((
+                match fence {
+                    "+++" => parse_toml(front_matter, bom + start)?,
+                    _ => parse_yaml(front_matter, bom + start)?,
+                },
+                rest,
+            ));
+        }
+    }
+}
+
+fn first_line(source: &str) -> &str {
+    source.lines().next().unwrap_or("").trim_end()
+}
+
+fn parse_toml(source: &str, start: usize) -> Result<FrontMatter, FrontMatterError> {
+    let table = source
+        .parse::<toml::Table>()
+        .map_err(|e| FrontMatterError::Toml(e, start))?;
+    Ok(table
+        .into_iter()
+        .map(|(key, value)| (key, toml_to_json(value)))
+        .collect())
+}
+
+fn toml_to_json(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(i) => Value::Number(i.into()),
+        toml::Value::Float(f) => Number::from_f64(f).map_or(Value::Null, Value::Number),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(d) => Value::String(d.to_string()),
+        toml::Value::Array(a) => Value::Array(a.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(t) => Value::Object(
+            t.into_iter()
+                .map(|(key, value)| (key, toml_to_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+fn parse_yaml(source: &str, start: usize) -> Result<FrontMatter, FrontMatterError> {
+    let docs = YamlLoader::load_from_str(source).map_err(|e| {
+        // The marker counts characters rather than bytes.
+        let index = source
+            .char_indices()
+            .nth(e.marker().index())
+            .map_or(source.len(), |(i, _)| i);
+        FrontMatterError::Yaml(e, start + index)
+    })?;
+    match docs.into_iter().next().map(yaml_to_json) {
+        Some(Value::Object(map)) => Ok(map),
+        None | Some(Value::Null) => Ok(FrontMatter::new()),
+        Some(_) => Err(FrontMatterError::NotATable),
+    }
+}
+
+fn yaml_to_json(value: Yaml) -> Value {
+    match value {
+        Yaml::String(s) => Value::String(s),
+        Yaml::Integer(i) => Value::Number(i.into()),
+        Yaml::Real(r) => r
+            .parse()
+            .ok()
+            .and_then(Number::from_f64)
+            .map_or(Value::String(r), Value::Number),
+        Yaml::Boolean(b) => Value::Bool(b),
+        Yaml::Array(a) => Value::Array(a.into_iter().map(yaml_to_json).collect()),
+        Yaml::Hash(h) => Value::Object(
+            h.into_iter()
+                .filter_map(|(key, value)| {
+                    let key = match key {
+                        Yaml::String(s) | Yaml::Real(s) => s,
+                        Yaml::Integer(i) => i.to_string(),
+                        Yaml::Boolean(b) => b.to_string(),
+                        _ => return None,
+                    };
+                    Some((key, yaml_to_json(value)))
+                })
+                .collect(),
+        ),
+        Yaml::Alias(_) | Yaml::Null | Yaml::BadValue => Value::Null,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn no_front_matter() {
+        let (front_matter, rest) = extract("<h1>Hi</h1>\n+++\n").unwrap();
+        assert!(front_matter.is_empty());
+        assert_eq!(rest, "<h1>Hi</h1>\n+++\n");
+    }
+
+    #[test]
+    fn toml() {
+        let source = "+++\ntitle = \"Hi\"\ntags = [\"a\", \"b\"]\ndate = 2024-01-31\n+++\n# Hi\n";
+        let (front_matter, rest) = extract(source).unwrap();
+        assert_eq!(front_matter["title"], json!("Hi"));
+        assert_eq!(front_matter["tags"], json!(["a", "b"]));
+        assert_eq!(front_matter["date"], json!("2024-01-31"));
+        assert_eq!(rest, "# Hi\n");
+    }
+
+    #[test]
+    fn yaml() {
+        let source = "---\r\ntitle: Hi\r\ncount: 2\r\ndraft: false\r\n---\r\nbody";
+        let (front_matter, rest) = extract(source).unwrap();
+        assert_eq!(front_matter["title"], json!("Hi"));
+        assert_eq!(front_matter["count"], json!(2));
+        assert_eq!(front_matter["draft"], json!(false));
+        assert_eq!(rest, "body");
+    }
+
+    #[test]
+    fn empty_and_at_the_end() {
+        let (front_matter, rest) = extract("---\n---").unwrap();
+        assert!(front_matter.is_empty());
+        assert_eq!(rest, "");
+    }
+
+    #[test]
+    fn byte_order_mark() {
+        let (front_matter, rest) = extract("\u{feff}+++\na = 1\n+++\nbody").unwrap();
+        assert_eq!(front_matter["a"], json!(1));
+        assert_eq!(rest, "body");
+    }
+
+    #[test]
+    fn unterminated() {
+        let error = extract("\u{feff}+++\na = 1\n").unwrap_err();
+        assert!(matches!(error, FrontMatterError::Unterminated("+++", 3)));
+        assert_eq!(error.span(), Some(Span { start: 3, end: 6 }));
+    }
+
+    #[test]
+    fn toml_error_points_into_the_file() {
+        let error = extract("+++\na = \n+++\n").unwrap_err();
+        assert!(matches!(error, FrontMatterError::Toml(_, 4)));
+        assert!(error.span().is_some_and(|span| span.start >= 4));
+    }
+
+    #[test]
+    fn yaml_must_be_a_table() {
+        let error = extract("---\n- a\n- b\n---\n").unwrap_err();
+        assert!(matches!(error, FrontMatterError::NotATable));
+    }
+}
@@ -0,0 +1,390 @@
+use std::cell::RefCell;
+
+use chrono::{
+    DateTime, FixedOffset, NaiveDate, NaiveDateTime,
+    format::{Item, StrftimeItems},
+};
+use lazy_static::lazy_static;
+use locrian::eval::{EvalContext, EvalFn, EvalResult};
+use serde_json::Value;
+
+use crate::{bindings::to_json_value, plugins};
+
+/// A function for bindings, which gets its arguments as JSON and fails with a reason.
+type Function = fn(&[Value]) -> Result<Value, String>;
+
+thread_local! {
+    /// Why the last function to fail did, since functions can only return a value. Bindings on
+    /// the same thread are evaluated one at a time, so it's theirs.
+    static ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+    /// The context from `context`, along with the generation of plugins it was made with.
+    static CONTEXT: RefCell<Option<(u64, EvalContext<'static>)>> = const { RefCell::new(None) };
+}
+
+/// Turns a `Function` into one that expressions can call.
+macro_rules! function {
+    ($name:literal, $function:path) => {{
+        fn call(args: Vec<EvalResult>) -> EvalResult {
+            let function: Function = $function;
+            finish($name, function(&json_args(&args)))
+        }
+        ($name, call as EvalFn)
+    }};
+}
+
+lazy_static! {
+    /// The standard library along with cheetah's own functions.
+    static ref BUILTINS: EvalContext<'static> = {
+        let mut ctx = locrian::stdlib::STDLIB.clone();
+        for (name, function) in [
+            function!("formatDate", format_date),
+            function!("slugify", slugify),
+            function!("urlJoin", url_join),
+            function!("assetUrl", asset_url),
+            function!("truncate", truncate),
+            function!("json", json),
+        ] {
+            ctx.fns.insert(name.to_string(), function);
+        }
+        ctx
+    };
+}
+
+/// How many functions plugins can add between them. Expressions can only call plain functions,
+/// which can't know which plugin function they stand for, so each plugin function is given one
+/// of these, which knows its index.
+pub const MAX_PLUGIN_FUNCTIONS: usize = 32;
+
+fn plugin_function<const I: usize>(args: Vec<EvalResult>) -> EvalResult {
+    let result = plugins::call_function(I, &json_args(&args)).map_err(|e| e.to_string());
+    match result {
+        Ok(value) => to_eval_result(&value),
+        Err(e) => fail(e),
+    }
+}
+
+const PLUGIN_FUNCTIONS: [EvalFn; MAX_PLUGIN_FUNCTIONS] = [
+    plugin_function::<0>,
+    plugin_function::<1>,
+    plugin_function::<2>,
+    plugin_function::<3>,
+    plugin_function::<4>,
+    plugin_function::<5>,
+    plugin_function::<6>,
+    plugin_function::<7>,
+    plugin_function::<8>,
+    plugin_function::<9>,
+    plugin_function::<10>,
+    plugin_function::<11>,
+    plugin_function::<12>,
+    plugin_function::<13>,
+    plugin_function::<14>,
+    plugin_function::<15>,
+    plugin_function::<16>,
+    plugin_function::<17>,
+    plugin_function::<18>,
+    plugin_function::<19>,
+    plugin_function::<20>,
+    plugin_function::<21>,
+    plugin_function::<22>,
+    plugin_function::<23>,
+    plugin_function::<24>,
+    plugin_function::<25>,
+    plugin_function::<26>,
+    plugin_function::<27>,
+    plugin_function::<28>,
+    plugin_function::<29>,
+    plugin_function::<30>,
+    plugin_function::<31>,
+];
+
+/// The context that every expression starts from: the standard library, cheetah's own
+/// functions, and the functions from plugins (which take precedence).
+pub fn context() -> EvalContext<'static> {
+    CONTEXT.with_borrow_mut(|cached| {
+        let generation = plugins::generation();
+        if let Some((made_with, ctx)) = cached
+            && *made_with == generation
+        {
+            return ctx.clone();
+        }
+        let mut ctx = BUILTINS.clone();
+        for (name, function) in plugins::functions().into_iter().zip(PLUGIN_FUNCTIONS) {
+            ctx.fns.insert(name, function);
+        }
+        *cached = Some((generation, ctx.clone()));
+        ctx
+    })
+}
+
+/// Takes the reason the last function failed, if one did since this was last called.
+pub fn take_error() -> Option<String> {
+    ERROR.take()
+}
+
+fn fail(reason: String) -> EvalResult<'static> {
+    ERROR.set(Some(reason));
+    EvalResult::None
+}
+
+fn finish(name: &str, result: Result<Value, String>) -> EvalResult<'static> {
+    match result {
+        Ok(value) => to_eval_result(&value),
+        Err(reason) => fail(format!("{name}: {reason}")),
+    }
+}
+
+fn json_args(args: &[EvalResult]) -> Vec<Value> {
+    args.iter()
+        .map(|arg| with_integers(to_json_value(arg)))
+        .collect()
+}
+
+/// Numbers in expressions are all floats, so this writes whole ones as integers, as they'd be
+/// written by hand.
+fn with_integers(value: Value) -> Value {
+    match value {
+        Value::Number(_) => whole_number(&value).map_or(value, Value::from),
+        Value::Array(a) => Value::Array(a.into_iter().map(with_integers).collect()),
+        Value::Object(o) => Value::Object(
+            o.into_iter()
+                .map(|(key, value)| (key, with_integers(value)))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+fn to_eval_result(value: &Value) -> EvalResult<'static> {
+    match value {
+        Value::Null => EvalResult::None,
+        Value::Bool(b) => EvalResult::Boolean(*b),
+        Value::Number(n) => n.as_f64().map_or(EvalResult::None, EvalResult::Number),
+        Value::String(s) => EvalResult::String(s.clone()),
+        Value::Array(a) => EvalResult::Array(a.iter().map(to_eval_result).collect()),
+        Value::Object(o) => EvalResult::Object(
+            o.iter()
+                .map(|(key, value)| (key.clone(), to_eval_result(value)))
+                .collect(),
+        ),
+    }
+}
+
+fn whole_number(value: &Value) -> Option<i64> {
+    value.as_i64().or_else(|| {
+        value
+            .as_f64()
+            .filter(|n| n.fract() == 0.0)
+            .map(|n| n as i64)
+    })
+}
+
+/// A string argument, or a number written as one.
+fn string_arg(args: &[Value], index: usize, what: &str) -> Result<String, String> {
+    match args.get(index) {
+        Some(Value::String(s)) => Ok(s.clone()),
+        Some(Value::Number(n)) => Ok(n.to_string()),
+        Some(other) => Err(format!("expected {what} to be a string, not {other}")),
+        None => Err(format!("missing {what}")),
+    }
+}
+
+/// Parses a date like the ones in front matter: `2024-01-31`, with a time or without, or a Unix
+/// timestamp. Dates without a time zone are taken to be in UTC.
+fn parse_date(value: &Value) -> Result<DateTime<FixedOffset>, String> {
+    let date = match value {
+        Value::Number(_) => whole_number(value)
+            .and_then(|secs| DateTime::from_timestamp(secs, 0))
+            .map(|date| date.fixed_offset()),
+        Value::String(s) => DateTime::parse_from_rfc3339(s).ok().or_else(|| {
+            ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+                .iter()
+                .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+                .or_else(|| {
+                    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                        .ok()
+                        .and_then(|date| date.and_hms_opt(0, 0, 0))
+                })
+                .map(|date| date.and_utc().fixed_offset())
+        }),
+        _ => None,
+    };
+    date.ok_or_else(|| format!("{value} is not a date"))
+}
+
+/// `formatDate(date, format)` formats a date with `strftime`-style specifiers, defaulting to
+/// `%Y-%m-%d`.
+fn format_date(args: &[Value]) -> Result<Value, String> {
+    let date = parse_date(args.first().ok_or("missing the date")?)?;
+    let format = match args.get(1) {
+        Some(_) => string_arg(args, 1, "the format")?,
+        None => "%Y-%m-%d".to_string(),
+    };
+    let items = StrftimeItems::new(&format).collect::<Vec<_>>();
+    if items.iter().any(|item| matches!(item, Item::Error)) {
+        return Err(format!("{format} is not a valid format"));
+    }
+    Ok(date.format_with_items(items.into_iter()).to_string().into())
+}
+
+/// `slugify(text)` lowercases text and replaces everything but letters and numbers with dashes,
+/// for URLs and IDs.
+fn slugify(args: &[Value]) -> Result<Value, String> {
+    let text = string_arg(args, 0, "the text")?;
+    let mut slug = String::new();
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    Ok(slug.trim_end_matches('-').into())
+}
+
+/// `urlJoin(a, b, ...)` joins parts of a URL with exactly one slash between each.
+fn url_join(args: &[Value]) -> Result<Value, String> {
+    let mut url = string_arg(args, 0, "the first part")?;
+    for index in 1..args.len() {
+        let part = string_arg(args, index, "each part")?;
+        if part.is_empty() {
+            continue;
+        }
+        url = format!(
+            "{}/{}",
+            url.trim_end_matches('/'),
+            part.trim_start_matches('/')
+        );
+    }
+    Ok(url.into())
+}
+
+/// `assetUrl(path)` gives the URL of a file in the assets directory.
+fn asset_url(args: &[Value]) -> Result<Value, String> {
+    let path = string_arg(args, 0, "the path")?;
+    Ok(format!("/assets/{}", path.trim_start_matches('/')).into())
+}
+
+/// `truncate(text, length, ending)` shortens text to at most `length` characters, followed by
+/// `ending` (an ellipsis by default) if anything was cut off.
+fn truncate(args: &[Value]) -> Result<Value, String> {
+    let text = string_arg(args, 0, "the text")?;
+    let length = args
+        .get(1)
+        .and_then(whole_number)
+        .and_then(|length| usize::try_from(length).ok())
+        .ok_or("expected the length to be a whole number")?;
+    let ending = match args.get(2) {
+        Some(_) => string_arg(args, 2, "the ending")?,
+        None => "…".to_string(),
+    };
+    if text.chars().count() <= length {
+        return Ok(text.into());
+    }
+    let cut = text.chars().take(length).collect::<String>();
+    Ok(format!("{}{ending}", cut.trim_end()).into())
+}
+
+/// `json(value)` writes a value as JSON, such as for passing it to a script.
+fn json(args: &[Value]) -> Result<Value, String> {
+    let value = args.first().ok_or("missing the value")?;
+    Ok(value.to_string().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn parses_dates() {
+        let date = |value| parse_date(&value).map(|date| date.to_rfc3339());
+        assert_eq!(
+            date(json!("2024-01-31")),
+            Ok("2024-01-31T00:00:00+00:00".into())
+        );
+        assert_eq!(
+            date(json!("2024-01-31 09:30")),
+            Ok("2024-01-31T09:30:00+00:00".into())
+        );
+        assert_eq!(
+            date(json!("2024-01-31T09:30:00+02:00")),
+            Ok("2024-01-31T09:30:00+02:00".into())
+        );
+        assert_eq!(date(json!(0.0)), Ok("1970-01-01T00:00:00+00:00".into()));
+        assert!(date(json!("tomorrow")).is_err());
+        assert!(date(json!(1.5)).is_err());
+    }
+
+    #[test]
+    fn formats_dates() {
+        assert_eq!(format_date(&[json!("2024-03-05")]), Ok(json!("2024-03-05")));
+        assert_eq!(
+            format_date(&[json!("2024-03-05"), json!("%B %-d, %Y")]),
+            Ok(json!("March 5, 2024"))
+        );
+        assert!(format_date(&[json!("2024-03-05"), json!("%Q")]).is_err());
+        assert!(format_date(&[]).is_err());
+    }
+
+    #[test]
+    fn slugifies() {
+        assert_eq!(slugify(&[json!("Hello, World!")]), Ok(json!("hello-world")));
+        assert_eq!(
+            slugify(&[json!("  Ünïcode -- ok ")]),
+            Ok(json!("ünïcode-ok"))
+        );
+        assert_eq!(slugify(&[json!(2024)]), Ok(json!("2024")));
+    }
+
+    #[test]
+    fn joins_urls() {
+        assert_eq!(
+            url_join(&[json!("https://a.com/"), json!("/b/"), json!(""), json!("c")]),
+            Ok(json!("https://a.com/b/c"))
+        );
+        assert_eq!(url_join(&[json!("/tags")]), Ok(json!("/tags")));
+        assert!(url_join(&[json!("/tags"), json!(null)]).is_err());
+    }
+
+    #[test]
+    fn asset_urls() {
+        assert_eq!(asset_url(&[json!("/x.css")]), Ok(json!("/assets/x.css")));
+    }
+
+    #[test]
+    fn truncates() {
+        assert_eq!(
+            truncate(&[json!("hello world"), json!(5)]),
+            Ok(json!("hello…"))
+        );
+        assert_eq!(
+            truncate(&[json!("hello world"), json!(6)]),
+            Ok(json!("hello…"))
+        );
+        assert_eq!(truncate(&[json!("hi"), json!(5.0)]), Ok(json!("hi")));
+        assert_eq!(
+            truncate(&[json!("héllo wörld"), json!(7), json!("...")]),
+            Ok(json!("héllo w..."))
+        );
+        assert!(truncate(&[json!("hi"), json!(-1)]).is_err());
+        assert!(truncate(&[json!("hi"), json!(1.5)]).is_err());
+    }
+
+    #[test]
+    fn writes_json() {
+        assert_eq!(
+            json(&[json!({ "a": [1, "b"] })]),
+            Ok(json!(r#"{"a":[1,"b"]}"#))
+        );
+    }
+
+    #[test]
+    fn whole_numbers_become_integers() {
+        assert_eq!(
+            with_integers(json!([1.0, 1.5, { "a": 2.0 }, "3.0"])),
+            json!([1, 1.5, { "a": 2 }, "3.0"])
+        );
+    }
+}
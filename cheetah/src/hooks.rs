@@ -1,69 +1,760 @@
-use std::{collections::HashMap, error::Error, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    error::Error,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
+use deno_task_shell::{KillSignal, ShellPipeReader, ShellState, SignalKind};
+use futures::{StreamExt, stream::FuturesUnordered};
 use indicatif::ProgressBar;
 use serde::{Deserialize, Serialize};
 use wax::{Glob, Pattern};
 
-use crate::config::SETTINGS;
+use crate::{config::SETTINGS, error};
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum During {
     PreBuild,
+    /// After each page is built.
+    PostPage,
+    /// After the assets are copied.
+    PostAssets,
     PostBuild,
+    /// Before the dev server starts serving the site.
+    PreServe,
+    /// When the build fails.
+    OnFailure,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum Dev {
     Watch(Vec<String>),
     Disabled,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Hook {
     name: String,
     during: During,
     dev: Dev,
     command: String,
+    /// Whether the build fails if the hook does. Defaults to true, except in the dev server.
+    #[serde(default)]
+    fail_on_error: Option<bool>,
+    /// How many seconds the hook can run for before it's stopped and counted as failed.
+    #[serde(default)]
+    timeout: Option<u64>,
+    /// Environment variables to run the hook with, on top of cheetah's own.
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    /// The directory to run the hook in, relative to the site root.
+    #[serde(default)]
+    cwd: Option<PathBuf>,
+    /// Globs of the files the hook reads. If any are set, the hook is skipped when neither these
+    /// nor its outputs have changed since it last succeeded.
+    #[serde(default)]
+    inputs: Vec<String>,
+    /// Globs of the files the hook writes.
+    #[serde(default)]
+    outputs: Vec<String>,
+    /// The names of the hooks that have to succeed before this one runs. Hooks that don't depend
+    /// on each other run at the same time.
+    #[serde(default)]
+    depends_on: Vec<String>,
 }
 
-pub async fn run_hook(hook: &Hook, progress: &ProgressBar) -> Result<i32, Box<dyn Error>> {
+/// Where the fingerprints of hooks that last succeeded are kept, in the out dir.
+const STATE_FILE: &str = "hooks.json";
+
+impl Hook {
+    /// Hashes the hook along with its inputs and outputs, so that it's unchanged if running the
+    /// hook again would do nothing. `None` if the hook doesn't declare its inputs.
+    fn fingerprint(
+        &self,
+        root: &Path,
+        context: &Context,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        if self.inputs.is_empty() {
+            return Ok(None);
+        }
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(serde_json::to_string(self)?.as_bytes());
+        if let Some((page, _)) = context.page {
+            hasher.update(page.as_os_str().as_encoded_bytes());
+        }
+        for glob in self.inputs.iter().chain(&self.outputs) {
+            let mut paths = Glob::new(glob)?
+                .walk(root)
+                .filter_map(Result::ok)
+                .map(|entry| entry.into_path())
+                .filter(|path| path.is_file())
+                .collect::<Vec<_>>();
+            paths.sort();
+            for path in paths {
+                hasher.update(path.as_os_str().as_encoded_bytes());
+                hasher.update(&fs::read(&path)?);
+            }
+        }
+        Ok(Some(hasher.finalize().to_hex().to_string()))
+    }
+
+    /// What the hook's fingerprint is saved under. `PostPage` hooks have one for each page.
+    fn state_key(&self, context: &Context) -> String {
+        match context.page {
+            Some((page, _)) => format!("{} {}", self.name, page.to_string_lossy()),
+            None => self.name.clone(),
+        }
+    }
+}
+
+fn load_state(path: &Path) -> BTreeMap<String, String> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|state| serde_json::from_str(&state).ok())
+        .unwrap_or_default()
+}
+
+/// Puts the hook's name before each line it printed, so that it's clear where it came from.
+fn prefixed(name: &str, output: &str) -> String {
+    output
+        .lines()
+        .map(|line| format!("\x1b[2m[{name}]\x1b[0m {line}\n"))
+        .collect()
+}
+
+#[derive(Debug)]
+pub enum HookError {
+    Failed(String, i32, String),
+    TimedOut(String, u64, String),
+}
+
+impl Error for HookError {}
+
+/// Hooks that were never run, because they depend on each other.
+#[derive(Debug)]
+pub struct HookCycle(Vec<String>);
+
+impl Error for HookCycle {}
+
+impl Display for HookCycle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.0[..] {
+            [hook] => write!(f, "Hook {hook} depends on itself"),
+            hooks => write!(f, "Hooks {} depend on each other", hooks.join(", ")),
+        }
+    }
+}
+
+/// Every hook that failed in a stage, since hooks that don't depend on each other keep running
+/// when one fails, along with any that couldn't run at all.
+#[derive(Debug)]
+pub struct HookErrors {
+    failed: Vec<Box<dyn Error>>,
+    cycle: Option<HookCycle>,
+}
+
+impl Error for HookErrors {}
+
+impl Display for HookErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.failed.len() {
+            1 => write!(f, "1 hook failed:")?,
+            n => write!(f, "{n} hooks failed:")?,
+        }
+        for error in &self.failed {
+            write!(f, "\n\n{error}")?;
+        }
+        if let Some(cycle) = &self.cycle {
+            write!(f, "\n\n{cycle}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Shows what the hook printed along with why it failed, since it's usually the only
+/// explanation.
+impl Display for HookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (name, output) = match self {
+            HookError::Failed(name, code, output) => {
+                write!(f, "Hook {name} failed with exit code {code}")?;
+                (name, output)
+            }
+            HookError::TimedOut(name, timeout, output) => {
+                write!(f, "Hook {name} timed out after {timeout}s")?;
+                (name, output)
+            }
+        };
+        let output = prefixed(name, output);
+        let output = output.trim_end();
+        if !output.is_empty() {
+            write!(f, "\n{output}")?;
+        }
+        Ok(())
+    }
+}
+
+/// What hooks are being run for, which they're told through environment variables.
+#[derive(Default, Clone, Copy)]
+pub struct Context<'a> {
+    /// Whether the site is being built by the dev server.
+    pub dev: bool,
+    /// The files whose changes the site is being rebuilt for, relative to the site root.
+    pub changed: Option<&'a [PathBuf]>,
+    /// The page that was just built, relative to the site root, and where it was written to.
+    pub page: Option<(&'a Path, &'a Path)>,
+    /// Why the build failed.
+    pub error: Option<&'a str>,
+}
+
+impl Context<'_> {
+    /// The environment variables that hooks are run with, besides cheetah's own.
+    fn env_vars(&self) -> Result<Vec<(&'static str, String)>, Box<dyn Error>> {
+        let pages_out = std::path::absolute(SETTINGS.lock()?.pages_out())?;
+        let mut vars = vec![("CHEETAH_OUT", pages_out.to_string_lossy().to_string())];
+        if self.dev {
+            vars.push(("CHEETAH_DEV", "1".into()));
+        }
+        if let Some(changed) = self.changed {
+            let changed = changed
+                .iter()
+                .map(|path| path.to_string_lossy())
+                .collect::<Vec<_>>();
+            vars.push(("CHEETAH_CHANGED", changed.join("\n")));
+        }
+        if let Some((page, out)) = self.page {
+            vars.push(("CHEETAH_PAGE", page.to_string_lossy().to_string()));
+            vars.push((
+                "CHEETAH_PAGE_OUT",
+                std::path::absolute(out)?.to_string_lossy().to_string(),
+            ));
+        }
+        if let Some(error) = self.error {
+            vars.push(("CHEETAH_ERROR", error::without_colors(error).to_string()));
+        }
+        Ok(vars)
+    }
+}
+
+/// Runs a hook, printing what it prints if it succeeds, unless its inputs haven't changed since
+/// it last succeeded.
+pub async fn run_hook(
+    hook: &Hook,
+    progress: &ProgressBar,
+    context: Context<'_>,
+) -> Result<(), Box<dyn Error>> {
+    let (root, state_file) = {
+        let settings = SETTINGS.lock()?;
+        let root = std::path::absolute(&settings.root)?;
+        (root, settings.resolve(&settings.out_dir).join(STATE_FILE))
+    };
+    let key = hook.state_key(&context);
+    if let Some(fingerprint) = hook.fingerprint(&root, &context)?
+        && load_state(&state_file).get(&key) == Some(&fingerprint)
+    {
+        return Ok(());
+    }
+
     progress.set_message(format!("Running hook \x1b[1m{}\x1b[0m", hook.name));
     let list = deno_task_shell::parser::parse(&hook.command)?;
 
-    let env_vars = std::env::vars_os().collect::<HashMap<_, _>>();
-    let cwd = std::env::current_dir()?;
+    let mut env_vars = std::env::vars_os().collect::<HashMap<_, _>>();
+    for (key, value) in &hook.env {
+        env_vars.insert(key.into(), value.into());
+    }
+    for (key, value) in context.env_vars()? {
+        env_vars.insert(key.into(), value.into());
+    }
+    let cwd = match &hook.cwd {
+        Some(cwd) => root.join(cwd),
+        None => root.clone(),
+    };
+
+    // Both stdout and stderr go to the same pipe, so that they stay in order.
+    let (reader, writer) = deno_task_shell::pipe();
+    let output = reader.pipe_to_string_handle();
+    let kill_signal = KillSignal::default();
+    let state = ShellState::new(env_vars, cwd, Default::default(), kill_signal.clone());
+    let execution = deno_task_shell::execute_with_pipes(
+        list,
+        state,
+        ShellPipeReader::stdin(),
+        writer.clone(),
+        writer,
+    );
+    tokio::pin!(execution);
+    let (exit_code, timed_out) = match hook.timeout {
+        Some(timeout) => {
+            match tokio::time::timeout(Duration::from_secs(timeout), &mut execution).await {
+                Ok(exit_code) => (exit_code, None),
+                Err(_) => {
+                    kill_signal.send(SignalKind::SIGKILL);
+                    (execution.await, Some(timeout))
+                }
+            }
+        }
+        None => (execution.await, None),
+    };
+    let output = output.await?;
 
-    let exit_code =
-        deno_task_shell::execute(list, env_vars, cwd, Default::default(), Default::default()).await;
+    if let Some(timeout) = timed_out {
+        return Err(HookError::TimedOut(hook.name.clone(), timeout, output).into());
+    }
+    if exit_code != 0 {
+        return Err(HookError::Failed(hook.name.clone(), exit_code, output).into());
+    }
+    if !output.is_empty() {
+        progress.suspend(|| print!("{}", prefixed(&hook.name, &output)));
+    }
 
-    Ok(exit_code)
+    // The outputs have changed now, so the fingerprint has to be taken again.
+    if let Some(fingerprint) = hook.fingerprint(&root, &context)? {
+        let mut state = load_state(&state_file);
+        state.insert(key, fingerprint);
+        if let Some(parent) = state_file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&state_file, serde_json::to_string_pretty(&state)?)?;
+    }
+    Ok(())
 }
 
+/// Runs the hooks for a stage, starting each as soon as the hooks it depends on have succeeded,
+/// with at most `hook_concurrency` running at once. If any fail, the rest that can still run are
+/// run before every failure is reported.
 pub async fn run_all(
     progress: &ProgressBar,
-    dev: bool,
-    file_changed_path: Option<&PathBuf>,
     at: During,
+    context: Context<'_>,
 ) -> Result<(), Box<dyn Error>> {
-    for hook in &SETTINGS.lock()?.hooks {
-        let run_in_dev = !dev
-            || (hook.dev != Dev::Disabled
-                && if let Some(path) = &file_changed_path {
-                    if let Dev::Watch(globs) = &hook.dev {
-                        globs
-                            .iter()
-                            .map(|glob| Glob::new(glob).map(|it| it.is_match(path.as_path())))
-                            .any(|r| matches!(r, Ok(true)))
+    let (hooks, concurrency) = {
+        let settings = SETTINGS.lock()?;
+        (settings.hooks.clone(), settings.hook_concurrency.max(1))
+    };
+    let mut pending = hooks
+        .iter()
+        .filter(|hook| {
+            let run_in_dev = !context.dev
+                || (hook.dev != Dev::Disabled
+                    && if let Some(changed) = context.changed {
+                        if let Dev::Watch(globs) = &hook.dev {
+                            globs
+                                .iter()
+                                .filter_map(|glob| Glob::new(glob).ok())
+                                .any(|glob| {
+                                    changed.iter().any(|path| glob.is_match(path.as_path()))
+                                })
+                        } else {
+                            true
+                        }
                     } else {
                         true
-                    }
-                } else {
-                    true
-                });
-        if run_in_dev && hook.during == at {
-            run_hook(hook, progress).await?;
+                    });
+            run_in_dev && hook.during == at
+        })
+        .collect::<Vec<_>>();
+    for hook in &pending {
+        if let Some(missing) = hook
+            .depends_on
+            .iter()
+            .find(|name| !hooks.iter().any(|other| &other.name == *name))
+        {
+            return Err(format!(
+                "Hook {} depends on {missing}, which doesn't exist",
+                hook.name
+            )
+            .into());
         }
     }
 
-    Ok(())
+    // Dependencies on hooks that aren't run this time are already met.
+    let mut succeeded = hooks
+        .iter()
+        .filter(|hook| !pending.iter().any(|pending| pending.name == hook.name))
+        .map(|hook| hook.name.as_str())
+        .collect::<HashSet<_>>();
+    let mut failed = HashSet::new();
+    let mut errors = Vec::new();
+    let mut running = FuturesUnordered::new();
+
+    loop {
+        // Hooks that depend on one that failed can't run at all.
+        while let Some(index) = pending.iter().position(|hook| {
+            hook.depends_on
+                .iter()
+                .any(|name| failed.contains(name.as_str()))
+        }) {
+            let hook = pending.remove(index);
+            progress.suspend(|| {
+                eprintln!(
+                    "\x1b[1;33mwarning:\x1b[0m Skipped hook {}, since a hook it depends on failed",
+                    hook.name
+                )
+            });
+            failed.insert(hook.name.as_str());
+        }
+        while running.len() < concurrency
+            && let Some(index) = pending.iter().position(|hook| {
+                hook.depends_on
+                    .iter()
+                    .all(|name| succeeded.contains(name.as_str()))
+            })
+        {
+            let hook = pending.remove(index);
+            running.push(async move { (hook, run_hook(hook, progress, context).await) });
+        }
+
+        let Some((hook, result)) = running.next().await else {
+            break;
+        };
+        match result {
+            Ok(()) => {
+                succeeded.insert(hook.name.as_str());
+            }
+            Err(e) if hook.fail_on_error.unwrap_or(!context.dev) => {
+                failed.insert(hook.name.as_str());
+                errors.push(e);
+            }
+            Err(e) => {
+                progress.suspend(|| eprintln!("\x1b[1;33mwarning:\x1b[0m {e}"));
+                succeeded.insert(hook.name.as_str());
+            }
+        }
+    }
+
+    let cycle = (!pending.is_empty())
+        .then(|| HookCycle(pending.iter().map(|hook| hook.name.clone()).collect()));
+    match (errors.len(), cycle) {
+        (0, None) => Ok(()),
+        (0, Some(cycle)) => Err(cycle.into()),
+        (1, None) => Err(errors.remove(0)),
+        (_, cycle) => Err(HookErrors {
+            failed: errors,
+            cycle,
+        }
+        .into()),
+    }
+}
+
+/// Runs the hooks for when the build fails with `error`. If they fail as well, that's only
+/// printed, so that it doesn't hide the error that caused them to run.
+pub async fn run_on_failure(progress: &ProgressBar, dev: bool, error: &str) {
+    let context = Context {
+        dev,
+        error: Some(error),
+        ..Default::default()
+    };
+    if let Err(e) = run_all(progress, During::OnFailure, context).await {
+        progress.suspend(|| eprintln!("\x1b[1;31merror:\x1b[0m {e}"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use serde_json::{Value, json};
+    use tokio::sync::{Mutex, MutexGuard};
+
+    use super::*;
+    use crate::{
+        config::Settings,
+        testing::{TempDir, settings},
+    };
+
+    /// Hooks are run with the global settings, so only one test can run them at a time.
+    static SITE: Mutex<()> = Mutex::const_new(());
+
+    /// Makes `hooks` (as they'd be written in `cheetah.toml`) the ones for a site in `dir`, until
+    /// the guard is dropped.
+    async fn use_hooks(dir: &TempDir, hooks: Value) -> MutexGuard<'static, ()> {
+        let guard = SITE.lock().await;
+        let hooks = hooks
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|hook| {
+                let mut hook = hook.clone();
+                hook["during"] = "PreBuild".into();
+                if hook.get("dev").is_none() {
+                    hook["dev"] = json!({ "Watch": [] });
+                }
+                serde_json::from_value(hook).unwrap()
+            })
+            .collect();
+        *SETTINGS.lock().unwrap() = Settings {
+            hooks,
+            ..settings(dir.path())
+        };
+        guard
+    }
+
+    async fn run(context: Context<'_>) -> Result<(), Box<dyn Error>> {
+        run_all(&ProgressBar::hidden(), During::PreBuild, context).await
+    }
+
+    /// What the hooks have appended to `log.txt`.
+    fn log(dir: &TempDir) -> String {
+        fs::read_to_string(dir.path().join("log.txt")).unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn failing_hooks_fail_the_build() {
+        let dir = TempDir::new();
+        let _site = use_hooks(
+            &dir,
+            json!([
+                { "name": "bad", "command": "echo oops && exit 2" },
+                { "name": "after", "command": "echo after >> log.txt", "depends_on": ["bad"] },
+            ]),
+        )
+        .await;
+        let error = run(Context::default()).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Hook bad failed with exit code 2\n\x1b[2m[bad]\x1b[0m oops"
+        );
+        assert_eq!(
+            log(&dir),
+            "",
+            "hooks that depend on a failed hook are skipped"
+        );
+    }
+
+    #[tokio::test]
+    async fn failing_hooks_only_warn_in_dev() {
+        let dir = TempDir::new();
+        let _site = use_hooks(
+            &dir,
+            json!([
+                { "name": "bad", "command": "exit 2" },
+                { "name": "after", "command": "echo after >> log.txt", "depends_on": ["bad"] },
+            ]),
+        )
+        .await;
+        let dev = Context {
+            dev: true,
+            ..Default::default()
+        };
+        run(dev).await.unwrap();
+        assert_eq!(log(&dir), "after\n");
+    }
+
+    #[tokio::test]
+    async fn fail_on_error() {
+        let dir = TempDir::new();
+        let site = use_hooks(
+            &dir,
+            json!([{ "name": "bad", "command": "exit 2", "fail_on_error": false }]),
+        )
+        .await;
+        run(Context::default()).await.unwrap();
+        drop(site);
+
+        let _site = use_hooks(
+            &dir,
+            json!([{ "name": "bad", "command": "exit 2", "fail_on_error": true }]),
+        )
+        .await;
+        let dev = Context {
+            dev: true,
+            ..Default::default()
+        };
+        assert!(run(dev).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn timeout() {
+        let dir = TempDir::new();
+        let _site = use_hooks(
+            &dir,
+            json!([{ "name": "slow", "command": "sleep 10", "timeout": 1 }]),
+        )
+        .await;
+        let start = Instant::now();
+        let error = run(Context::default()).await.unwrap_err();
+        assert_eq!(error.to_string(), "Hook slow timed out after 1s");
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn unchanged_inputs_skip_the_hook() {
+        let dir = TempDir::new();
+        dir.write("src/a.txt", "A");
+        let _site = use_hooks(
+            &dir,
+            json!([{
+                "name": "copy",
+                "command": "echo ran >> log.txt && cp src/a.txt out.txt",
+                "inputs": ["src/*.txt"],
+                "outputs": ["out.txt"],
+            }]),
+        )
+        .await;
+        run(Context::default()).await.unwrap();
+        run(Context::default()).await.unwrap();
+        assert_eq!(log(&dir), "ran\n");
+
+        dir.write("src/a.txt", "Changed");
+        run(Context::default()).await.unwrap();
+        assert_eq!(log(&dir), "ran\nran\n");
+
+        dir.write("out.txt", "Edited by hand");
+        run(Context::default()).await.unwrap();
+        assert_eq!(log(&dir), "ran\nran\nran\n", "changed outputs run it again");
+    }
+
+    #[tokio::test]
+    async fn hooks_without_inputs_always_run() {
+        let dir = TempDir::new();
+        let _site = use_hooks(
+            &dir,
+            json!([{ "name": "log", "command": "echo ran >> log.txt", "outputs": ["log.txt"] }]),
+        )
+        .await;
+        run(Context::default()).await.unwrap();
+        run(Context::default()).await.unwrap();
+        assert_eq!(log(&dir), "ran\nran\n");
+    }
+
+    #[tokio::test]
+    async fn env_and_cwd() {
+        let dir = TempDir::new();
+        dir.write("sub/.keep", "");
+        let _site = use_hooks(
+            &dir,
+            json!([{
+                "name": "env",
+                "command": "echo $GREETING $CHEETAH_DEV > log.txt",
+                "env": { "GREETING": "hi" },
+                "cwd": "sub",
+            }]),
+        )
+        .await;
+        let dev = Context {
+            dev: true,
+            ..Default::default()
+        };
+        run(dev).await.unwrap();
+        let log = fs::read_to_string(dir.path().join("sub/log.txt")).unwrap();
+        assert_eq!(log, "hi 1\n");
+    }
+
+    #[tokio::test]
+    async fn dependencies_run_first() {
+        let dir = TempDir::new();
+        let _site = use_hooks(
+            &dir,
+            json!([
+                { "name": "c", "command": "echo c >> log.txt", "depends_on": ["b"] },
+                { "name": "b", "command": "echo b >> log.txt", "depends_on": ["a"] },
+                { "name": "a", "command": "sleep 0.2 && echo a >> log.txt" },
+            ]),
+        )
+        .await;
+        run(Context::default()).await.unwrap();
+        assert_eq!(log(&dir), "a\nb\nc\n");
+    }
+
+    #[tokio::test]
+    async fn independent_hooks_run_at_once() {
+        let dir = TempDir::new();
+        let _site = use_hooks(
+            &dir,
+            json!([
+                { "name": "a", "command": "sleep 1" },
+                { "name": "b", "command": "sleep 1" },
+            ]),
+        )
+        .await;
+        let start = Instant::now();
+        run(Context::default()).await.unwrap();
+        assert!(start.elapsed() < Duration::from_millis(1800));
+    }
+
+    #[tokio::test]
+    async fn cycles() {
+        let dir = TempDir::new();
+        let site = use_hooks(
+            &dir,
+            json!([
+                { "name": "x", "command": "echo x >> log.txt", "depends_on": ["y"] },
+                { "name": "y", "command": "echo y >> log.txt", "depends_on": ["x"] },
+                { "name": "z", "command": "echo z >> log.txt" },
+            ]),
+        )
+        .await;
+        let error = run(Context::default()).await.unwrap_err();
+        assert!(error.is::<HookCycle>());
+        assert_eq!(error.to_string(), "Hooks x, y depend on each other");
+        assert_eq!(log(&dir), "z\n", "hooks outside the cycle still run");
+        drop(site);
+
+        let _site = use_hooks(
+            &dir,
+            json!([
+                { "name": "x", "command": "exit 0", "depends_on": ["x"] },
+                { "name": "bad", "command": "exit 1" },
+            ]),
+        )
+        .await;
+        let error = run(Context::default()).await.unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "1 hook failed:\n\nHook bad failed with exit code 1\n\nHook x depends on itself"
+        );
+    }
+
+    #[tokio::test]
+    async fn every_failure_is_reported() {
+        let dir = TempDir::new();
+        let _site = use_hooks(
+            &dir,
+            json!([
+                { "name": "a", "command": "exit 1" },
+                { "name": "b", "command": "exit 2" },
+            ]),
+        )
+        .await;
+        let error = run(Context::default()).await.unwrap_err().to_string();
+        assert!(error.starts_with("2 hooks failed:"));
+        assert!(error.contains("Hook a failed") && error.contains("Hook b failed"));
+    }
+
+    #[tokio::test]
+    async fn watch_globs_in_dev() {
+        let dir = TempDir::new();
+        let _site = use_hooks(
+            &dir,
+            json!([
+                { "name": "css", "command": "echo css >> log.txt", "dev": { "Watch": ["**/*.css"] } },
+                { "name": "off", "command": "echo off >> log.txt", "dev": "Disabled" },
+            ]),
+        )
+        .await;
+        fn changed(changed: Option<&[PathBuf]>) -> Context<'_> {
+            Context {
+                dev: true,
+                changed,
+                ..Default::default()
+            }
+        }
+        let page = [PathBuf::from("pages/a.md")];
+        run(changed(Some(&page))).await.unwrap();
+        assert_eq!(log(&dir), "");
+        let stylesheet = [PathBuf::from("assets/a.css")];
+        run(changed(Some(&stylesheet))).await.unwrap();
+        assert_eq!(log(&dir), "css\n");
+        run(changed(None)).await.unwrap();
+        assert_eq!(
+            log(&dir),
+            "css\ncss\n",
+            "everything runs for the first build"
+        );
+        run(Context::default()).await.unwrap();
+        assert_eq!(log(&dir), "css\ncss\ncss\noff\n");
+    }
 }
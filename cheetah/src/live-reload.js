@@ -0,0 +1,94 @@
+/*
+ * live-reload.js
+ * part of the Cheetah static site generator (https://cheetah.farthergate.com)
+ * copyright (C) 2025 Aleks Rūtiņš <aleks@rutins.com> under the MIT License
+ */
+
+const events = new EventSource("/_cheetah/events");
+
+let overlay = null;
+
+function hideErrors() {
+  overlay?.remove();
+  overlay = null;
+}
+
+// The overlay is in a shadow root, so that the page's styles don't apply to it.
+function showErrors(errors) {
+  hideErrors();
+  overlay = document.createElement("cheetah-error-overlay");
+  let shadow = overlay.attachShadow({ mode: "open" });
+  shadow.innerHTML = `
+    <style>
+      .backdrop {
+        position: fixed;
+        inset: 0;
+        z-index: 2147483647;
+        display: flex;
+        align-items: flex-start;
+        justify-content: center;
+        padding: 5vh 1rem;
+        overflow: auto;
+        background: rgba(0, 0, 0, 0.6);
+      }
+      .window {
+        max-width: 100%;
+        padding: 1rem 1.5rem;
+        border-top: 4px solid #e5484d;
+        border-radius: 4px;
+        background: #1c1c1c;
+        color: #eee;
+        font: 14px/1.5 ui-monospace, SFMono-Regular, Menlo, Consolas, monospace;
+      }
+      h1 {
+        margin: 0 0 1rem;
+        color: #ff6369;
+        font-size: 1.1rem;
+      }
+      pre {
+        margin: 0;
+        overflow-x: auto;
+        white-space: pre;
+        tab-size: 4;
+      }
+      p {
+        margin: 1rem 0 0;
+        color: #999;
+      }
+      button {
+        font: inherit;
+      }
+    </style>
+    <div class="backdrop">
+      <div class="window">
+        <h1>Build failed</h1>
+        <pre></pre>
+        <p>Fix the error and save to rebuild, or <button>dismiss</button> this for now.</p>
+      </div>
+    </div>
+  `;
+  shadow.querySelector("pre").textContent = errors;
+  shadow.querySelector("button").addEventListener("click", hideErrors);
+  document.documentElement.append(overlay);
+}
+
+events.addEventListener("reload", () => location.reload());
+
+events.addEventListener("build-error", (event) => showErrors(event.data));
+
+events.addEventListener("css", (event) => {
+  hideErrors();
+  for (let link of document.querySelectorAll('link[rel="stylesheet"]')) {
+    let url = new URL(link.href);
+    if (url.origin != location.origin || url.pathname != event.data) {
+      continue;
+    }
+    url.searchParams.set("t", Date.now());
+    // Only remove the old stylesheet once the new one has loaded, so that the page doesn't flash
+    // unstyled.
+    let replacement = link.cloneNode();
+    replacement.href = url;
+    replacement.addEventListener("load", () => link.remove());
+    link.after(replacement);
+  }
+});
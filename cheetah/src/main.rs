@@ -1,32 +1,116 @@
 use std::{
     cell::RefCell,
     collections::HashMap,
-    env,
     error::Error,
     fmt::Display,
     fs,
-    path::PathBuf,
+    path::{Path, PathBuf},
     rc::Rc,
+    sync::Arc,
     time::{Duration, SystemTime},
 };
 
+use clap::{Args, Parser, Subcommand};
+use collections::PageIndex;
+use config::{OVERRIDES, SETTINGS};
+use dependencies::Dependencies;
+use error::BuildError;
 use indexmap::IndexMap;
 use indicatif::{ProgressBar, ProgressStyle};
-use template::TemplateLoader;
+use manifest::{Hashes, Manifest, Output};
+use rayon::prelude::*;
+use template::{ElementRegistrar, TemplateLoader};
 
 extern crate html5ever;
 mod bindings;
+mod collections;
 mod config;
+mod dependencies;
+mod error;
+mod frontmatter;
+mod functions;
 mod hooks;
+mod manifest;
 mod markdown;
+mod plugins;
+mod props;
 mod server;
 mod template;
+#[cfg(test)]
+mod testing;
+mod wasm;
 
-const BUILD_DIR: &str = "_build";
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Directory containing the site [default: current directory]
+    #[arg(long, global = true)]
+    root: Option<PathBuf>,
+
+    /// Directory containing pages, relative to the root [default: pages]
+    #[arg(long, global = true)]
+    pages_dir: Option<PathBuf>,
+
+    /// Directory containing components, relative to the root [default: components]
+    #[arg(long, global = true)]
+    components_dir: Option<PathBuf>,
+
+    /// Directory containing layouts, relative to the root [default: layouts]
+    #[arg(long, global = true)]
+    layouts_dir: Option<PathBuf>,
+
+    /// Directory containing assets, relative to the root [default: assets]
+    #[arg(long, global = true)]
+    assets_dir: Option<PathBuf>,
+
+    /// Directory to write the built site to [default: _build in the root]
+    #[arg(long, global = true)]
+    out_dir: Option<PathBuf>,
+
+    /// Configuration file to use instead of cheetah.toml
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Build the site (the default if no subcommand is given)
+    Build,
+    /// Build the site, then serve it and rebuild whenever a file changes
+    Dev(ServerArgs),
+    /// Serve an already-built site without building or watching it
+    Serve(ServerArgs),
+    /// Remove the build directory
+    Clean,
+    /// Create a new site
+    New {
+        /// Directory to create the site in
+        path: PathBuf,
+    },
+}
+
+#[derive(Args)]
+struct ServerArgs {
+    /// Address for the server to listen on [default: 127.0.0.1]
+    #[arg(long)]
+    host: Option<String>,
+
+    /// Port for the server to listen on, or the first to try if it's taken [default: 3000]
+    #[arg(long)]
+    port: Option<u16>,
+}
 
 #[derive(Debug)]
 enum CompileError {
     NotAFileNameError,
+    SiteExists(PathBuf),
+    Page(PathBuf, Box<BuildError>),
+    Bindings(PathBuf, Vec<BuildError>),
+    /// Several pages failed, which are all reported so that they can be fixed together.
+    Pages(Vec<CompileError>),
 }
 
 impl Error for CompileError {}
@@ -35,59 +119,292 @@ impl Display for CompileError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             CompileError::NotAFileNameError => write!(f, "Not a file name"),
+            CompileError::SiteExists(path) => {
+                write!(f, "{} already exists and is not empty", path.display())
+            }
+            CompileError::Page(path, e) => {
+                write!(f, "Failed to build {}: {e}", path.display())
+            }
+            CompileError::Bindings(path, errors) => {
+                write!(f, "Failed to build {}: ", path.display())?;
+                match errors.len() {
+                    1 => write!(f, "1 binding failed")?,
+                    n => write!(f, "{n} bindings failed")?,
+                }
+                for e in errors {
+                    write!(f, "\n\n{e}")?;
+                }
+                Ok(())
+            }
+            CompileError::Pages(errors) => {
+                write!(f, "{} pages failed to build", errors.len())?;
+                for e in errors {
+                    write!(f, "\n\n{e}")?;
+                }
+                Ok(())
+            }
         }
     }
 }
 
-fn copy_assets_recursive(dir: String, progress: &ProgressBar) -> Result<(), Box<dyn Error>> {
-    if let Ok(assets) = fs::read_dir(dir.clone()) {
-        fs::create_dir_all("_build/pages/assets")?;
+/// Copies the assets in `dir` (relative to the site root) into the output, except for the ones
+/// that are unchanged since the build that `previous` is from.
+fn copy_assets_recursive(
+    dir: PathBuf,
+    previous: &Manifest,
+    manifest: &mut Manifest,
+    hashes: &mut Hashes,
+    progress: &ProgressBar,
+) -> Result<(), Box<dyn Error>> {
+    let (root, out_dir) = {
+        let settings = SETTINGS.lock().unwrap();
+        (settings.root.clone(), settings.resolve(&settings.out_dir))
+    };
+    if let Ok(assets) = fs::read_dir(root.join(&dir)) {
         for asset in assets.flatten() {
+            let path = dir.join(asset.file_name());
             if asset.file_type()?.is_dir() {
-                let full_path = format!("{}/{}", dir, asset.file_name().to_string_lossy());
-                fs::create_dir_all(format!("_build/pages/{full_path}"))?;
-                copy_assets_recursive(full_path, progress)?;
+                copy_assets_recursive(path, previous, manifest, hashes, progress)?;
+            } else if previous.is_fresh(&path, hashes, &out_dir) {
+                manifest.reuse(previous, &path);
             } else {
-                progress.set_message(format!(
-                    "Copying asset \x1b[1m{}\x1b[0m",
-                    asset.path().to_string_lossy()
-                ));
-                fs::copy(
-                    asset.path(),
-                    format!(
-                        "_build/pages/{}/{}",
-                        dir,
-                        asset.file_name().to_string_lossy()
-                    ),
-                )?;
+                copy_asset(&path, manifest, hashes, progress)?;
             }
         }
     }
 
     Ok(())
 }
+
+/// Copies a single asset (relative to the site root) into the output.
+fn copy_asset(
+    path: &Path,
+    manifest: &mut Manifest,
+    hashes: &mut Hashes,
+    progress: &ProgressBar,
+) -> Result<(), Box<dyn Error>> {
+    let (source, out, out_dir) = {
+        let settings = SETTINGS.lock().unwrap();
+        (
+            settings.resolve(path),
+            settings
+                .pages_out()
+                .join("assets")
+                .join(path.strip_prefix(&settings.assets_dir)?),
+            settings.resolve(&settings.out_dir),
+        )
+    };
+    progress.set_message(format!(
+        "Copying asset \x1b[1m{}\x1b[0m",
+        path.to_string_lossy()
+    ));
+    fs::create_dir_all(out.parent().unwrap())?;
+    fs::copy(source, &out)?;
+    manifest.outputs.insert(
+        out.strip_prefix(&out_dir)?.to_path_buf(),
+        Output {
+            source: Some(path.to_path_buf()),
+            hash: hashes.get(path).unwrap_or_default(),
+        },
+    );
+    Ok(())
+}
+
+/// What building a page produced.
+struct BuiltPage {
+    registrars: HashMap<String, ElementRegistrar>,
+    dependencies: Dependencies,
+    output: PathBuf,
+    hash: String,
+}
+
+/// Fails the page with every binding that failed on it in strict mode, or else prints them as
+/// warnings.
+fn check_bindings(
+    path: &Path,
+    errors: Vec<BuildError>,
+    strict: bool,
+    progress: &ProgressBar,
+) -> Result<(), CompileError> {
+    if strict && !errors.is_empty() {
+        return Err(CompileError::Bindings(path.to_path_buf(), errors));
+    }
+    for e in errors {
+        progress.suspend(|| eprintln!("\x1b[1;33mwarning:\x1b[0m {e}"));
+    }
+    Ok(())
+}
+
+/// Renders a page and writes it out.
 fn compile_template(
     path: PathBuf,
     loader: &TemplateLoader,
+    pages: &Arc<PageIndex>,
     progress: &ProgressBar,
-) -> Result<(), Box<dyn Error>> {
-    let template = loader.load(&path.to_string_lossy().to_string())?;
+) -> Result<BuiltPage, CompileError> {
+    let template = loader
+        .load(&path)
+        .map_err(|e| CompileError::Page(path.clone(), BuildError::new(e).into()))?;
     progress.set_message(format!(
         "Building page \x1b[1m{}\x1b[0m",
         path.to_string_lossy()
     ));
-    let mut html_path = path;
-    html_path.set_extension("html");
-    let out_path = format!("{}/{}", BUILD_DIR, html_path.to_string_lossy());
-    let (output, scripts) = template.render_to_string(&template::TemplateContext {
-        loader: loader.clone(),
-        contents: None,
-        component_name: None,
-        attrs: IndexMap::new(),
-        scripts: Rc::new(RefCell::new(HashMap::new())),
-    })?;
-    fs::write(out_path, format!("<!doctype html>{output}"))?;
-    for (script_name, registrar) in scripts {
+    let (out_path, strict, always_hydrate) = {
+        let settings = SETTINGS.lock().unwrap();
+        (
+            settings.page_out(&path).with_extension("html"),
+            settings.strict,
+            settings.always_hydrate,
+        )
+    };
+    let dev = OVERRIDES.lock().unwrap().dev;
+    let binding_errors = Rc::new(RefCell::new(vec![]));
+    let dependencies = Rc::new(RefCell::new(Dependencies::default()));
+    let (dom, scripts) = template
+        .render(&template::TemplateContext {
+            loader: loader.clone(),
+            contents: None,
+            component_name: None,
+            attrs: IndexMap::new(),
+            vars: template::Scope::default(),
+            pages: pages.clone(),
+            scripts: Rc::new(RefCell::new(HashMap::new())),
+            binding_errors: binding_errors.clone(),
+            chain: vec![],
+            dependencies: dependencies.clone(),
+            always_hydrate,
+        })
+        .map_err(|e| {
+            CompileError::Page(
+                path.clone(),
+                BuildError::at(e, template.file_location()).into(),
+            )
+        })?;
+    check_bindings(&path, binding_errors.take(), strict, progress)?;
+    let page = plugins::Page {
+        path: &path,
+        output: &out_path,
+        front_matter: &template.front_matter,
+        dev,
+    };
+    plugins::transform_dom(&dom, &page)
+        .map_err(|e| CompileError::Page(path.clone(), BuildError::new(e).into()))?;
+    let output = format!("<!doctype html>{dom}");
+    // A page that's new since the output directories were created may be in a new directory.
+    if let Some(parent) = out_path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| CompileError::Page(path.clone(), BuildError::new(e).into()))?;
+    }
+    fs::write(&out_path, &output)
+        .map_err(|e| CompileError::Page(path.clone(), BuildError::new(e).into()))?;
+    plugins::on_page_written(&page)
+        .map_err(|e| CompileError::Page(path, BuildError::new(e).into()))?;
+    Ok(BuiltPage {
+        registrars: scripts,
+        dependencies: dependencies.take(),
+        output: out_path,
+        hash: manifest::hash(output),
+    })
+}
+
+/// Finds every page under `dir`, creating the directories that they'll be written to.
+fn find_pages(dir: PathBuf, loader: &TemplateLoader) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut pages = vec![];
+    if let Ok(entries) = fs::read_dir(loader.resolve(&dir)) {
+        for entry in entries.flatten() {
+            let full_path = dir.join(
+                entry
+                    .file_name()
+                    .to_str()
+                    .ok_or(CompileError::NotAFileNameError)?,
+            );
+            if entry.file_type()?.is_dir() {
+                let out_path = SETTINGS.lock().unwrap().page_out(&full_path);
+                fs::create_dir_all(out_path)?;
+                pages.extend(find_pages(full_path, loader)?);
+            } else {
+                pages.push(full_path);
+            }
+        }
+    }
+
+    Ok(pages)
+}
+
+/// Renders pages in parallel and records what each one was built from and wrote in `manifest`,
+/// then runs the hooks for each page and writes the scripts for the components hydrated on any
+/// page in the manifest. `changed` is the files that the dev server is rebuilding for, if it is.
+async fn compile_pages(
+    paths: Vec<PathBuf>,
+    changed: Option<&[PathBuf]>,
+    loader: &TemplateLoader,
+    pages: &Arc<PageIndex>,
+    manifest: &mut Manifest,
+    progress: &ProgressBar,
+) -> Result<(), Box<dyn Error>> {
+    let (pages_out, out_dir) = {
+        let settings = SETTINGS.lock().unwrap();
+        (settings.pages_out(), settings.resolve(&settings.out_dir))
+    };
+    let results = paths
+        .into_par_iter()
+        .map(|path| {
+            let result = compile_template(path.clone(), loader, pages, progress);
+            (path, result)
+        })
+        .collect::<Vec<_>>();
+
+    let dev = OVERRIDES.lock().unwrap().dev;
+    let mut errors = vec![];
+    for (path, result) in results {
+        match result {
+            Ok(page) => {
+                let context = hooks::Context {
+                    dev,
+                    changed,
+                    page: Some((&path, &page.output)),
+                    ..Default::default()
+                };
+                hooks::run_all(progress, hooks::During::PostPage, context).await?;
+                manifest.outputs.insert(
+                    page.output.strip_prefix(&out_dir)?.to_path_buf(),
+                    Output {
+                        source: Some(path.clone()),
+                        hash: page.hash,
+                    },
+                );
+                manifest.graph.insert(path.clone(), page.dependencies);
+                manifest.failed.remove(&path);
+                manifest.registrars.insert(path, page.registrars);
+            }
+            Err(e) => {
+                manifest.failed.insert(path);
+                errors.push(e);
+            }
+        }
+    }
+    match errors.len() {
+        0 => {}
+        1 => return Err(errors.remove(0).into()),
+        _ => return Err(CompileError::Pages(errors).into()),
+    }
+
+    // A component used on several pages may hydrate differently on each of them.
+    let mut registrars = HashMap::<String, ElementRegistrar>::new();
+    for (script_name, registrar) in manifest.registrars.values().flatten() {
+        let registrar = registrar.clone();
+        match registrars.get_mut(script_name) {
+            Some(existing) => existing
+                .connected_scripts
+                .extend(registrar.connected_scripts),
+            None => {
+                registrars.insert(script_name.clone(), registrar);
+            }
+        }
+    }
+
+    manifest.outputs.retain(|_, output| output.source.is_some());
+    for (script_name, registrar) in registrars {
         let mut scripts = registrar.connected_scripts;
         scripts.sort();
         scripts.dedup();
@@ -104,45 +421,170 @@ registerComponent(`{}`, [{}]);
                 .collect::<Vec<_>>()
                 .join(", ")
         );
-        fs::write(format!("_build/pages/_scripts/{script_name}"), contents)?;
+        let script_path = pages_out.join("_scripts").join(script_name);
+        if fs::read_to_string(&script_path).ok().as_ref() != Some(&contents) {
+            fs::write(&script_path, &contents)?;
+        }
+        manifest.outputs.insert(
+            script_path.strip_prefix(&out_dir)?.to_path_buf(),
+            Output {
+                source: None,
+                hash: manifest::hash(contents),
+            },
+        );
     }
     Ok(())
 }
 
-fn compile_templates_recursive(
-    dir: String,
-    loader: &TemplateLoader,
-    progress: &ProgressBar,
-) -> Result<(), Box<dyn Error>> {
-    if let Ok(pages) = fs::read_dir(loader.resolve(&dir)) {
-        for entry in pages.flatten() {
-            let full_path = PathBuf::from(format!(
-                "{}/{}",
-                dir,
-                entry
-                    .file_name()
-                    .to_str()
-                    .ok_or(CompileError::NotAFileNameError)?
-            ));
-            if entry.file_type()?.is_dir() {
-                let out_path = format!("{}/{}", BUILD_DIR, full_path.to_string_lossy());
-                fs::create_dir_all(out_path)?;
-                compile_templates_recursive(
-                    full_path.to_string_lossy().to_string(),
-                    loader,
-                    progress,
-                )?;
-            } else {
-                compile_template(full_path, loader, progress)?;
-            }
-        }
+async fn build(progress: &ProgressBar) -> Result<(), Box<dyn Error>> {
+    let result = build_site(progress).await;
+    if let Err(e) = &result {
+        hooks::run_on_failure(progress, false, &e.to_string()).await;
+    }
+    result
+}
+
+async fn build_site(progress: &ProgressBar) -> Result<(), Box<dyn Error>> {
+    let start = SystemTime::now();
+    let (loader, root, pages_dir, assets_dir, pages_out, out_dir) = {
+        let settings = SETTINGS.lock().unwrap();
+        (
+            TemplateLoader::new(&settings),
+            settings.root.clone(),
+            settings.pages_dir.clone(),
+            settings.assets_dir.clone(),
+            settings.pages_out(),
+            settings.resolve(&settings.out_dir),
+        )
+    };
+
+    fs::create_dir_all(pages_out.join("_scripts"))?;
+
+    hooks::run_all(progress, hooks::During::PreBuild, Default::default()).await?;
+    // Plugins may have just been built by a hook.
+    plugins::load_configured(&SETTINGS.lock().unwrap())?;
+
+    progress.set_message("Indexing pages");
+    let pages = Arc::new(PageIndex::build(&loader, &SETTINGS.lock().unwrap())?);
+
+    let (mut manifest, previous) = {
+        let settings = SETTINGS.lock().unwrap();
+        let manifest = Manifest::new(&settings, &pages)?;
+        let previous = Manifest::load(&settings, &manifest);
+        (manifest, previous)
+    };
+    // Pages that list others have to be rebuilt whenever any page's index entry changes.
+    let listing_pages = if previous.index == manifest.index {
+        vec![]
+    } else {
+        previous.graph.listing_pages()
+    };
+    let mut hashes = Hashes::new(root);
+    let (unchanged, changed): (Vec<_>, Vec<_>) = find_pages(pages_dir, &loader)?
+        .into_iter()
+        .partition(|path| {
+            !listing_pages.contains(path) && previous.is_fresh(path, &mut hashes, &out_dir)
+        });
+    for path in &unchanged {
+        manifest.reuse(&previous, path);
+    }
+    let built = changed.clone();
+    compile_pages(changed, None, &loader, &pages, &mut manifest, progress).await?;
+
+    fs::write(
+        pages_out.join("_scripts/component.js"),
+        include_str!("component.js"),
+    )?;
+
+    copy_assets_recursive(assets_dir, &previous, &mut manifest, &mut hashes, progress)?;
+    hooks::run_all(progress, hooks::During::PostAssets, Default::default()).await?;
+
+    manifest.remove_stale(&previous, &out_dir)?;
+    manifest.record_inputs(&mut hashes);
+    manifest.save(&SETTINGS.lock().unwrap())?;
+
+    plugins::on_build_finished(&plugins::Build {
+        out: &pages_out,
+        pages: &built,
+        dev: false,
+    })?;
+    hooks::run_all(progress, hooks::During::PostBuild, Default::default()).await?;
+
+    let unchanged = match unchanged.len() {
+        0 => String::new(),
+        1 => " (1 page unchanged)".to_string(),
+        n => format!(" ({n} pages unchanged)"),
+    };
+    progress.finish_with_message(format!(
+        "Built in \x1b[1m{}ms\x1b[0m{unchanged}",
+        SystemTime::now().duration_since(start).unwrap().as_millis()
+    ));
+
+    Ok(())
+}
+
+fn clean(progress: &ProgressBar) -> Result<(), Box<dyn Error>> {
+    let out_dir = {
+        let settings = SETTINGS.lock().unwrap();
+        settings.resolve(&settings.out_dir)
+    };
+    if out_dir.exists() {
+        fs::remove_dir_all(&out_dir)?;
     }
+    progress.finish_with_message(format!("Removed \x1b[1m{}\x1b[0m", out_dir.display()));
+    Ok(())
+}
 
+fn new_site(path: &Path, progress: &ProgressBar) -> Result<(), Box<dyn Error>> {
+    if fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_some()) {
+        return Err(CompileError::SiteExists(path.to_path_buf()).into());
+    }
+    for dir in ["pages", "layouts", "components", "assets"] {
+        fs::create_dir_all(path.join(dir))?;
+    }
+    fs::write(
+        path.join("cheetah.toml"),
+        include_str!("scaffold/cheetah.toml"),
+    )?;
+    fs::write(
+        path.join("layouts/index.html"),
+        include_str!("scaffold/index.html"),
+    )?;
+    fs::write(
+        path.join("pages/index.md"),
+        include_str!("scaffold/index.md"),
+    )?;
+    progress.finish_with_message(format!(
+        "Created a new site in \x1b[1m{}\x1b[0m",
+        path.display()
+    ));
     Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+    let (host, port) = match &cli.command {
+        Some(Command::Dev(server) | Command::Serve(server)) => (server.host.clone(), server.port),
+        _ => (None, None),
+    };
+
+    *config::OVERRIDES.lock().unwrap() = config::Overrides {
+        root: cli.root,
+        config: cli.config,
+        pages_dir: cli.pages_dir,
+        components_dir: cli.components_dir,
+        layouts_dir: cli.layouts_dir,
+        assets_dir: cli.assets_dir,
+        out_dir: cli.out_dir.map(std::path::absolute).transpose()?,
+        host,
+        port,
+        dev: matches!(cli.command, Some(Command::Dev(_))),
+    };
+    let settings = config::Settings::new()?;
+    *SETTINGS.lock().unwrap() = settings;
+    plugins::register(server::LiveReloadScript)?;
+
     let progress = ProgressBar::new_spinner();
     progress.enable_steady_tick(Duration::from_millis(120));
     progress.set_style(
@@ -151,33 +593,69 @@ async fn main() -> Result<(), Box<dyn Error>> {
             .tick_strings(&["⣾", "⣽", "⣻", "⢿", "⡿", "⣟", "⣯", "⣷", "✓"]),
     );
 
-    let args: Vec<String> = env::args().collect();
-    if args.len() > 1 && args[1] == *"dev" {
-        server::run(progress).await
-    } else {
-        let start = SystemTime::now();
-        let loader = TemplateLoader::default();
-
-        fs::create_dir_all("_build/pages/_scripts")?;
-
-        hooks::run_all(&progress, false, None, hooks::During::PreBuild).await?;
-
-        compile_templates_recursive("pages".to_string(), &loader, &progress)?;
+    let result = match cli.command.unwrap_or(Command::Build) {
+        Command::Build => build(&progress).await,
+        Command::Dev(_) => server::run(progress.clone()).await,
+        Command::Serve(_) => server::serve(&progress).await,
+        Command::Clean => clean(&progress),
+        Command::New { path } => new_site(&path, &progress),
+    };
+    if let Err(e) = result {
+        progress.abandon_with_message("\x1b[1;31mFailed\x1b[0m");
+        eprintln!("\x1b[1;31merror:\x1b[0m {e}");
+        std::process::exit(1);
+    }
 
-        fs::write(
-            "_build/pages/_scripts/component.js",
-            include_str!("component.js"),
-        )?;
+    Ok(())
+}
 
-        copy_assets_recursive("assets".to_string(), &progress)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{template::Template, testing::context};
 
-        hooks::run_all(&progress, false, None, hooks::During::PostBuild).await?;
+    /// The bindings that fail when rendering `html`, which are all collected rather than
+    /// stopping at the first.
+    fn failed_bindings(html: &str) -> Vec<BuildError> {
+        let ctx = context(Path::new(""));
+        Template::from_html(html.to_string())
+            .unwrap()
+            .render(&ctx)
+            .unwrap();
+        ctx.binding_errors.take()
+    }
 
-        progress.finish_with_message(format!(
-            "Built in \x1b[1m{}ms\x1b[0m",
-            SystemTime::now().duration_since(start).unwrap().as_millis()
-        ));
+    #[test]
+    fn strict_fails_with_every_binding() {
+        let errors = failed_bindings(r#"<p [title]="a">{{ b }} {{ "fine" }} {{ c }}</p>"#);
+        let error = check_bindings(
+            Path::new("pages/index.html"),
+            errors,
+            true,
+            &ProgressBar::hidden(),
+        )
+        .unwrap_err();
+        let CompileError::Bindings(_, errors) = &error else {
+            panic!("expected failed bindings, got {error}");
+        };
+        assert_eq!(errors.len(), 3);
+        assert!(
+            error
+                .to_string()
+                .starts_with("Failed to build pages/index.html: 3 bindings failed")
+        );
+    }
 
-        Ok(())
+    #[test]
+    fn warnings_without_strict() {
+        let errors = failed_bindings("<p>{{ missing }}</p>");
+        assert_eq!(errors.len(), 1);
+        let result = check_bindings(
+            Path::new("pages/index.html"),
+            errors,
+            false,
+            &ProgressBar::hidden(),
+        );
+        assert!(result.is_ok());
     }
 }
@@ -0,0 +1,342 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    collections::PageIndex, config::Settings, dependencies::DependencyGraph,
+    template::ElementRegistrar,
+};
+
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// A record of what the last build read and wrote, kept in the output directory so that the next
+/// build can skip the pages and assets that haven't changed since.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Manifest {
+    /// The version of cheetah, and a hash of the settings, that the site was built with. If either
+    /// changes, nothing from the previous build can be reused.
+    pub version: String,
+    pub settings: String,
+    /// A hash of the page index, which the pages that list other pages were built from.
+    pub index: String,
+    /// Hashes of the pages, templates, and assets that were read, relative to the site root.
+    pub inputs: BTreeMap<PathBuf, String>,
+    pub graph: DependencyGraph,
+    /// The components hydrated on each page.
+    pub registrars: BTreeMap<PathBuf, HashMap<String, ElementRegistrar>>,
+    /// Everything that was written, relative to the output directory.
+    pub outputs: BTreeMap<PathBuf, Output>,
+    /// The pages that couldn't be built. What they depend on isn't known, so the dev server tries
+    /// them again after every change.
+    #[serde(skip)]
+    pub failed: BTreeSet<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Output {
+    /// The page or asset the output was built from, if it was built from just one.
+    pub source: Option<PathBuf>,
+    pub hash: String,
+}
+
+/// Hashes of files, so that each is only read once per build.
+pub struct Hashes {
+    root: PathBuf,
+    hashes: HashMap<PathBuf, Option<String>>,
+}
+
+pub fn hash(contents: impl AsRef<[u8]>) -> String {
+    blake3::hash(contents.as_ref()).to_hex().to_string()
+}
+
+impl Hashes {
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            hashes: HashMap::new(),
+        }
+    }
+
+    /// The hash of a file relative to `root`, or `None` if it can't be read.
+    pub fn get(&mut self, path: &Path) -> Option<String> {
+        self.hashes
+            .entry(path.to_path_buf())
+            .or_insert_with(|| fs::read(self.root.join(path)).ok().map(hash))
+            .clone()
+    }
+}
+
+impl Manifest {
+    pub fn new(settings: &Settings, index: &PageIndex) -> Result<Self, Box<dyn Error>> {
+        // Plugins change what's built without the settings changing when they're rebuilt.
+        let plugins = settings
+            .plugins
+            .iter()
+            .map(|plugin| {
+                fs::read(settings.resolve(&plugin.path))
+                    .map(hash)
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<_>>();
+        // Where the site is, how it's served, and how it's built don't change what's built from it.
+        let mut settings = serde_json::to_value(settings)?;
+        if let Value::Object(settings) = &mut settings {
+            for key in [
+                "root",
+                "out_dir",
+                "host",
+                "port",
+                "clean_urls",
+                "hook_concurrency",
+            ] {
+                settings.remove(key);
+            }
+        }
+        Ok(Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            settings: hash(format!("{settings}{}", plugins.join(""))),
+            index: hash(serde_json::to_string(index)?),
+            ..Default::default()
+        })
+    }
+
+    /// Reads the manifest left by the last build, if there is one and it can be used for a build
+    /// like `current`.
+    pub fn load(settings: &Settings, current: &Manifest) -> Self {
+        fs::read_to_string(settings.resolve(&settings.out_dir).join(MANIFEST_FILE))
+            .ok()
+            .and_then(|manifest| serde_json::from_str::<Manifest>(&manifest).ok())
+            .filter(|previous| {
+                previous.version == current.version && previous.settings == current.settings
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, settings: &Settings) -> Result<(), Box<dyn Error>> {
+        fs::write(
+            settings.resolve(&settings.out_dir).join(MANIFEST_FILE),
+            serde_json::to_string_pretty(self)?,
+        )?;
+        Ok(())
+    }
+
+    /// Records the hashes of every page, template, and asset that the outputs were built from.
+    pub fn record_inputs(&mut self, hashes: &mut Hashes) {
+        let sources = self
+            .outputs
+            .values()
+            .filter_map(|output| output.source.clone())
+            .collect::<Vec<_>>();
+        for source in sources {
+            let templates = self
+                .graph
+                .get(&source)
+                .map(|dependencies| dependencies.templates.clone())
+                .unwrap_or_default();
+            for input in templates.into_iter().chain([source]) {
+                if let Some(hash) = hashes.get(&input) {
+                    self.inputs.insert(input, hash);
+                }
+            }
+        }
+    }
+
+    /// Whether `source`, and every template it was built from, are the same as they were in this
+    /// build, and what was built from it is still there unchanged in `out_dir`.
+    pub fn is_fresh(&self, source: &Path, hashes: &mut Hashes, out_dir: &Path) -> bool {
+        let mut outputs = self
+            .outputs
+            .iter()
+            .filter(|(_, output)| output.source.as_deref() == Some(source))
+            .peekable();
+        if outputs.peek().is_none() {
+            return false;
+        }
+        let outputs_unchanged = outputs.all(|(path, output)| {
+            fs::read(out_dir.join(path)).is_ok_and(|contents| hash(contents) == output.hash)
+        });
+        let templates = self
+            .graph
+            .get(source)
+            .map(|dependencies| dependencies.templates.iter().collect::<Vec<_>>())
+            .unwrap_or_default();
+        outputs_unchanged
+            && templates
+                .into_iter()
+                .chain([&source.to_path_buf()])
+                .all(|input| self.inputs.get(input) == hashes.get(input).as_ref())
+    }
+
+    /// Carries over everything about `source` from the manifest of a previous build.
+    pub fn reuse(&mut self, previous: &Manifest, source: &Path) {
+        if let Some(dependencies) = previous.graph.get(source) {
+            self.graph
+                .insert(source.to_path_buf(), dependencies.clone());
+        }
+        if let Some(registrars) = previous.registrars.get(source) {
+            self.registrars
+                .insert(source.to_path_buf(), registrars.clone());
+        }
+        for (path, output) in &previous.outputs {
+            if output.source.as_deref() == Some(source) {
+                self.outputs.insert(path.clone(), output.clone());
+            }
+        }
+    }
+
+    /// Removes everything that a previous build wrote and this one didn't, like the pages and
+    /// assets whose sources have been deleted.
+    pub fn remove_stale(&self, previous: &Manifest, out_dir: &Path) -> Result<(), Box<dyn Error>> {
+        for path in previous.outputs.keys() {
+            if !self.outputs.contains_key(path) {
+                remove_output(out_dir, path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Forgets `source` (or everything under it, if it was a directory) and removes what was
+    /// built from it, for when it's been deleted.
+    pub fn remove_source(&mut self, source: &Path, out_dir: &Path) -> Result<(), Box<dyn Error>> {
+        self.graph.remove(source);
+        self.failed.retain(|page| !page.starts_with(source));
+        self.registrars.retain(|page, _| !page.starts_with(source));
+        let removed = self
+            .outputs
+            .iter()
+            .filter(|(_, output)| {
+                output
+                    .source
+                    .as_ref()
+                    .is_some_and(|s| s.starts_with(source))
+            })
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        for path in removed {
+            self.outputs.remove(&path);
+            remove_output(out_dir, &path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Removes an output, and any directories that are left empty by removing it.
+fn remove_output(out_dir: &Path, path: &Path) -> Result<(), Box<dyn Error>> {
+    let full_path = out_dir.join(path);
+    if full_path.exists() {
+        fs::remove_file(&full_path)?;
+    }
+    for dir in full_path.ancestors().skip(1) {
+        if dir == out_dir || fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dependencies::Dependencies, testing::TempDir};
+
+    fn output(source: &str, contents: &str) -> Output {
+        Output {
+            source: Some(source.into()),
+            hash: hash(contents),
+        }
+    }
+
+    /// A site with a page built from a layout, and a manifest of building it.
+    fn built() -> (TempDir, Manifest) {
+        let dir = TempDir::new();
+        dir.write("pages/a.html", "A");
+        dir.write("layouts/base.html", "Base");
+        dir.write("_build/a.html", "Built A");
+        let mut manifest = Manifest::default();
+        manifest
+            .outputs
+            .insert("a.html".into(), output("pages/a.html", "Built A"));
+        manifest.graph.insert(
+            "pages/a.html".into(),
+            Dependencies {
+                templates: [PathBuf::from("layouts/base.html")].into(),
+                pages: false,
+            },
+        );
+        manifest.record_inputs(&mut Hashes::new(dir.path().to_path_buf()));
+        (dir, manifest)
+    }
+
+    fn is_fresh(dir: &TempDir, manifest: &Manifest, source: &str) -> bool {
+        manifest.is_fresh(
+            Path::new(source),
+            &mut Hashes::new(dir.path().to_path_buf()),
+            &dir.path().join("_build"),
+        )
+    }
+
+    #[test]
+    fn fresh_until_the_page_changes() {
+        let (dir, manifest) = built();
+        assert!(is_fresh(&dir, &manifest, "pages/a.html"));
+        dir.write("pages/a.html", "Changed");
+        assert!(!is_fresh(&dir, &manifest, "pages/a.html"));
+    }
+
+    #[test]
+    fn stale_once_a_template_changes() {
+        let (dir, manifest) = built();
+        dir.write("layouts/base.html", "Changed");
+        assert!(!is_fresh(&dir, &manifest, "pages/a.html"));
+    }
+
+    #[test]
+    fn stale_once_the_output_changes() {
+        let (dir, manifest) = built();
+        dir.write("_build/a.html", "Edited by hand");
+        assert!(!is_fresh(&dir, &manifest, "pages/a.html"));
+        fs::remove_file(dir.path().join("_build/a.html")).unwrap();
+        assert!(!is_fresh(&dir, &manifest, "pages/a.html"));
+    }
+
+    #[test]
+    fn never_built_is_stale() {
+        let (dir, manifest) = built();
+        dir.write("pages/new.html", "New");
+        assert!(!is_fresh(&dir, &manifest, "pages/new.html"));
+    }
+
+    #[test]
+    fn remove_stale_outputs() {
+        let (dir, current) = built();
+        dir.write("_build/old/b.html", "Built B");
+        dir.write("_build/old/keep.txt", "Not from a build");
+        dir.write("_build/gone/c.html", "Built C");
+        let mut previous = Manifest::default();
+        previous
+            .outputs
+            .insert("a.html".into(), output("pages/a.html", "Built A"));
+        previous
+            .outputs
+            .insert("old/b.html".into(), output("pages/old/b.html", "Built B"));
+        previous
+            .outputs
+            .insert("gone/c.html".into(), output("pages/gone/c.html", "Built C"));
+
+        let out_dir = dir.path().join("_build");
+        current.remove_stale(&previous, &out_dir).unwrap();
+        assert!(out_dir.join("a.html").exists());
+        assert!(!out_dir.join("old/b.html").exists());
+        assert!(out_dir.join("old/keep.txt").exists());
+        assert!(
+            !out_dir.join("gone").exists(),
+            "directories left empty are removed"
+        );
+    }
+}
@@ -0,0 +1,353 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    path::{Path, PathBuf},
+    sync::{
+        RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use kuchikikiki::NodeRef;
+use lazy_static::lazy_static;
+use serde_json::Value;
+
+use crate::{config::Settings, frontmatter::FrontMatter, functions, wasm::WasmPlugin};
+
+lazy_static! {
+    /// The plugins that sites are built with, in the order they run.
+    static ref PLUGINS: RwLock<Vec<Box<dyn Plugin>>> = RwLock::new(vec![]);
+    /// The plugins in `cheetah.toml`, which run after the ones registered in code and are
+    /// replaced whenever it's loaded again.
+    static ref CONFIGURED: RwLock<Vec<Box<dyn Plugin>>> = RwLock::new(vec![]);
+}
+
+/// How many times the plugins have changed, so that templates parsed with older ones aren't
+/// reused.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// The page a plugin is being called for.
+pub struct Page<'a> {
+    /// The page, relative to the site root.
+    pub path: &'a Path,
+    /// Where the page is written to.
+    pub output: &'a Path,
+    pub front_matter: &'a FrontMatter,
+    /// Whether the site is being built by the dev server.
+    pub dev: bool,
+}
+
+/// The build a plugin is being told has finished.
+pub struct Build<'a> {
+    /// The directory the site was built into.
+    pub out: &'a Path,
+    /// The pages that were built, relative to the site root. Pages that hadn't changed since the
+    /// last build aren't included.
+    pub pages: &'a [PathBuf],
+    pub dev: bool,
+}
+
+/// Extends the build from inside cheetah, unlike hooks, which can only run commands around it.
+/// Pages are built in parallel, so plugins are called from several threads at once.
+///
+/// Every callback does nothing by default, so plugins only need to implement the ones they use.
+/// An error from any of them fails the page or build it was called for.
+pub trait Plugin: Send + Sync {
+    /// The name of the plugin, for errors.
+    fn name(&self) -> &str;
+
+    /// Called with the source of every page, layout and component before it's parsed, with the
+    /// path relative to the site root. Returns the source to parse instead, if the plugin changes
+    /// it.
+    fn on_load(&self, _path: &Path, _source: &str) -> Result<Option<String>, Box<dyn Error>> {
+        Ok(None)
+    }
+
+    /// Called with each page's document once it's been rendered, before it's written, so that
+    /// the plugin can change it.
+    fn transform_dom(&self, _dom: &NodeRef, _page: &Page) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// Called after each page is written.
+    fn on_page_written(&self, _page: &Page) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// Called once the whole site has been built, or rebuilt by the dev server.
+    fn on_build_finished(&self, _build: &Build) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// The names of the functions that the plugin adds to expressions in bindings.
+    fn functions(&self) -> Vec<String> {
+        vec![]
+    }
+
+    /// Calls one of the plugin's functions with the values it was given.
+    fn call_function(&self, name: &str, _args: &[Value]) -> Result<Value, Box<dyn Error>> {
+        Err(format!("it has no function called {name}").into())
+    }
+}
+
+#[derive(Debug)]
+pub struct PluginError {
+    plugin: String,
+    error: String,
+}
+
+impl PluginError {
+    fn new(plugin: &dyn Plugin, error: Box<dyn Error>) -> Self {
+        Self {
+            plugin: plugin.name().to_string(),
+            error: error.to_string(),
+        }
+    }
+}
+
+impl Error for PluginError {}
+
+impl Display for PluginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Plugin {} failed: {}", self.plugin, self.error)
+    }
+}
+
+/// Adds a plugin to the ones that sites are built with, after the ones already registered.
+pub fn register(plugin: impl Plugin + 'static) -> Result<(), Box<dyn Error>> {
+    let mut registered = PLUGINS.write().unwrap();
+    let plugin: Box<dyn Plugin> = Box::new(plugin);
+    check_functions(
+        registered
+            .iter()
+            .chain(CONFIGURED.read().unwrap().iter())
+            .chain([&plugin]),
+    )?;
+    registered.push(plugin);
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Fails if `plugins` add more functions between them than expressions can be given.
+fn check_functions<'a>(plugins: impl Iterator<Item = &'a Box<dyn Plugin>>) -> Result<(), String> {
+    let count = plugins
+        .map(|plugin| plugin.functions().len())
+        .sum::<usize>();
+    if count > functions::MAX_PLUGIN_FUNCTIONS {
+        return Err(format!(
+            "Plugins can only add {} functions between them, not {count}",
+            functions::MAX_PLUGIN_FUNCTIONS
+        ));
+    }
+    Ok(())
+}
+
+/// Loads the plugins in `cheetah.toml`, in place of the ones that were loaded before.
+pub fn load_configured(settings: &Settings) -> Result<(), Box<dyn Error>> {
+    let plugins = settings
+        .plugins
+        .iter()
+        .map(|config| {
+            WasmPlugin::load(&settings.root, config)
+                .map(|plugin| Box::new(plugin) as Box<dyn Plugin>)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    // Plugins registered in code have functions too, which count towards the same limit.
+    check_functions(PLUGINS.read().unwrap().iter().chain(&plugins))?;
+    *CONFIGURED.write().unwrap() = plugins;
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+}
+
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::Relaxed)
+}
+
+/// Calls `f` with every plugin in the order they run, stopping at the first that fails.
+fn for_each(
+    mut f: impl FnMut(&dyn Plugin) -> Result<(), Box<dyn Error>>,
+) -> Result<(), PluginError> {
+    let registered = PLUGINS.read().unwrap();
+    let configured = CONFIGURED.read().unwrap();
+    for plugin in registered.iter().chain(configured.iter()) {
+        f(plugin.as_ref()).map_err(|e| PluginError::new(plugin.as_ref(), e))?;
+    }
+    Ok(())
+}
+
+/// Runs every plugin's `on_load`, each on the source left by the one before.
+pub fn on_load(path: &Path, source: String) -> Result<String, PluginError> {
+    let mut source = source;
+    for_each(|plugin| {
+        if let Some(changed) = plugin.on_load(path, &source)? {
+            source = changed;
+        }
+        Ok(())
+    })?;
+    Ok(source)
+}
+
+pub fn transform_dom(dom: &NodeRef, page: &Page) -> Result<(), PluginError> {
+    for_each(|plugin| plugin.transform_dom(dom, page))
+}
+
+pub fn on_page_written(page: &Page) -> Result<(), PluginError> {
+    for_each(|plugin| plugin.on_page_written(page))
+}
+
+pub fn on_build_finished(build: &Build) -> Result<(), PluginError> {
+    for_each(|plugin| plugin.on_build_finished(build))
+}
+
+/// The names of every plugin's functions, in the order the plugins run.
+pub fn functions() -> Vec<String> {
+    let mut functions = vec![];
+    // Listing functions can't fail.
+    let _ = for_each(|plugin| {
+        functions.extend(plugin.functions());
+        Ok(())
+    });
+    functions
+}
+
+/// Calls the function at `index` in `functions()`.
+pub fn call_function(index: usize, args: &[Value]) -> Result<Value, PluginError> {
+    let mut index = Some(index);
+    let mut value = Value::Null;
+    for_each(|plugin| {
+        let Some(i) = index else {
+            return Ok(());
+        };
+        let functions = plugin.functions();
+        match functions.get(i) {
+            Some(name) => {
+                value = plugin.call_function(name, args)?;
+                index = None;
+            }
+            None => index = Some(i - functions.len()),
+        }
+        Ok(())
+    })?;
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Once;
+
+    use kuchikikiki::traits::TendrilSink;
+
+    use super::*;
+    use crate::{template::Template, testing::context};
+
+    struct Functions(usize);
+
+    impl Plugin for Functions {
+        fn name(&self) -> &str {
+            "functions"
+        }
+
+        fn functions(&self) -> Vec<String> {
+            (0..self.0).map(|i| format!("f{i}")).collect()
+        }
+    }
+
+    #[test]
+    fn register_checks_the_function_limit() {
+        let error = register(Functions(functions::MAX_PLUGIN_FUNCTIONS + 1)).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("Plugins can only add 32 functions between them")
+        );
+        assert!(
+            !functions().contains(&"f0".to_string()),
+            "the plugin isn't registered"
+        );
+    }
+
+    /// The plugins are shared by every test, so these only change the page they're tested on.
+    const PAGE: &str = "plugin-test.html";
+
+    /// Marks what it's given with its name, and adds functions named after it.
+    struct Marker(&'static str, usize);
+
+    impl Plugin for Marker {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn on_load(&self, path: &Path, source: &str) -> Result<Option<String>, Box<dyn Error>> {
+            Ok((path == Path::new(PAGE)).then(|| format!("{source}[{}]", self.0)))
+        }
+
+        fn transform_dom(&self, dom: &NodeRef, page: &Page) -> Result<(), Box<dyn Error>> {
+            if page.path == Path::new(PAGE) {
+                dom.append(NodeRef::new_text(format!("[{}]", self.0)));
+            }
+            Ok(())
+        }
+
+        fn functions(&self) -> Vec<String> {
+            (0..self.1).map(|i| format!("{}Test{i}", self.0)).collect()
+        }
+
+        fn call_function(&self, name: &str, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+            Ok(format!("{name}({})", Value::from(args)).into())
+        }
+    }
+
+    fn register_markers() {
+        static REGISTERED: Once = Once::new();
+        REGISTERED.call_once(|| {
+            register(Marker("first", 2)).unwrap();
+            register(Marker("second", 1)).unwrap();
+        });
+    }
+
+    #[test]
+    fn run_in_the_order_registered() {
+        register_markers();
+        assert_eq!(
+            on_load(Path::new(PAGE), "<p>".into()).unwrap(),
+            "<p>[first][second]"
+        );
+        assert_eq!(
+            on_load(Path::new("other.html"), "<p>".into()).unwrap(),
+            "<p>"
+        );
+
+        let dom = kuchikikiki::parse_html().one("");
+        let front_matter = FrontMatter::new();
+        let page = Page {
+            path: Path::new(PAGE),
+            output: Path::new("_build/plugin-test.html"),
+            front_matter: &front_matter,
+            dev: false,
+        };
+        transform_dom(&dom, &page).unwrap();
+        assert!(dom.to_string().ends_with("[first][second]"));
+    }
+
+    #[test]
+    fn functions_are_called_on_their_plugin() {
+        register_markers();
+        let functions = functions();
+        for name in ["firstTest0", "firstTest1", "secondTest0"] {
+            let index = functions.iter().position(|f| f == name).unwrap();
+            assert_eq!(
+                call_function(index, &[Value::from(1)]).unwrap(),
+                format!("{name}([1])")
+            );
+        }
+        assert_eq!(call_function(functions.len(), &[]).unwrap(), Value::Null);
+    }
+
+    #[test]
+    fn bindings_call_plugin_functions() {
+        register_markers();
+        let template = Template::from_html("{{ secondTest0(2) }}".into()).unwrap();
+        let (root, _) = template.render(&context(Path::new(""))).unwrap();
+        assert_eq!(root.text_contents(), "secondTest0([2])");
+    }
+}
@@ -0,0 +1,285 @@
+use std::{error::Error, fmt::Display};
+
+use indexmap::IndexMap;
+use kuchikikiki::{Attribute, ExpandedName};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::template::{Template, Vars};
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PropType {
+    #[default]
+    String,
+    Number,
+    #[serde(alias = "boolean")]
+    Bool,
+    #[serde(alias = "array")]
+    List,
+}
+
+impl Display for PropType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Spelled the way they're declared.
+        let name = match self {
+            PropType::String => "string",
+            PropType::Number => "number",
+            PropType::Bool => "bool",
+            PropType::List => "list",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct PropSpec {
+    #[serde(rename = "type", default)]
+    pub prop_type: PropType,
+    #[serde(default)]
+    pub default: Option<Value>,
+    #[serde(default)]
+    pub required: bool,
+}
+
+/// A prop can be declared with just its type (`title = "string"`) or with a table.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum PropDeclaration {
+    Type(PropType),
+    Spec(PropSpec),
+}
+
+#[derive(Debug)]
+pub enum PropError {
+    InvalidDeclaration(String, serde_json::Error),
+    Missing(String, String),
+    Invalid(String, String, PropType, String),
+}
+
+impl Error for PropError {}
+
+impl Display for PropError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PropError::InvalidDeclaration(component, e) => {
+                write!(f, "<{component}> has invalid props: {e}")
+            }
+            PropError::Missing(component, prop) => {
+                write!(f, "<{component}> is missing its required prop `{prop}`")
+            }
+            PropError::Invalid(component, prop, prop_type, value) => write!(
+                f,
+                "<{component}> expects prop `{prop}` to be a {prop_type}, but got `{value}`"
+            ),
+        }
+    }
+}
+
+/// Reads the `props` table from a component's front matter.
+pub fn declared(
+    template: &Template,
+    component: &str,
+) -> Result<Vec<(String, PropSpec)>, PropError> {
+    let Some(props) = template.front_matter.get("props") else {
+        return Ok(vec![]);
+    };
+    let declarations: serde_json::Map<String, Value> = serde_json::from_value(props.clone())
+        .map_err(|e| PropError::InvalidDeclaration(component.to_string(), e))?;
+    declarations
+        .into_iter()
+        .map(|(name, declaration)| {
+            let spec = match serde_json::from_value(declaration)
+                .map_err(|e| PropError::InvalidDeclaration(component.to_string(), e))?
+            {
+                PropDeclaration::Type(prop_type) => PropSpec {
+                    prop_type,
+                    ..Default::default()
+                },
+                PropDeclaration::Spec(spec) => spec,
+            };
+            Ok((name, spec))
+        })
+        .collect()
+}
+
+/// Checks the attributes given to a component against the props it declares, and converts them
+/// to variables of the declared types.
+pub fn resolve(
+    template: &Template,
+    component: &str,
+    attrs: &IndexMap<ExpandedName, Attribute>,
+) -> Result<Vars, PropError> {
+    let mut vars = Vars::new();
+    for (name, spec) in declared(template, component)? {
+        // HTML attribute names are case-insensitive, and the parser lowercases them.
+        let attr = attrs
+            .iter()
+            .find(|(key, _)| key.local.as_ref().eq_ignore_ascii_case(&name))
+            .map(|(_, attr)| attr.value.as_str());
+        let value = match (attr, &spec.default) {
+            (Some(attr), _) => parse(attr, spec.prop_type).ok_or_else(|| {
+                PropError::Invalid(
+                    component.to_string(),
+                    name.clone(),
+                    spec.prop_type,
+                    attr.to_string(),
+                )
+            })?,
+            (None, _) if spec.required => {
+                return Err(PropError::Missing(component.to_string(), name));
+            }
+            (None, Some(default)) => default.clone(),
+            (None, None) if spec.prop_type == PropType::Bool => Value::Bool(false),
+            (None, None) if spec.prop_type == PropType::List => Value::Array(vec![]),
+            (None, None) => Value::Null,
+        };
+        vars.insert(name, value);
+    }
+    Ok(vars)
+}
+
+fn parse(attr: &str, prop_type: PropType) -> Option<Value> {
+    match prop_type {
+        PropType::String => Some(Value::String(attr.to_string())),
+        PropType::Number => attr
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map(Value::Number),
+        // Like boolean attributes in HTML, a bool prop is true just by being there.
+        PropType::Bool => match attr.trim() {
+            "false" => Some(Value::Bool(false)),
+            _ => Some(Value::Bool(true)),
+        },
+        PropType::List => match serde_json::from_str(attr) {
+            Ok(Value::Array(items)) => Some(Value::Array(items)),
+            Ok(_) => None,
+            Err(_) if attr.trim().is_empty() => Some(Value::Array(vec![])),
+            Err(_) => Some(Value::Array(
+                attr.split(',')
+                    .map(|item| Value::String(item.trim().to_string()))
+                    .collect(),
+            )),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// Resolves the attributes `attrs` against `props`, as declared in front matter.
+    fn resolve_with(props: Value, attrs: &[(&str, &str)]) -> Result<Value, PropError> {
+        let mut template = Template::from_html(String::new()).unwrap();
+        template.front_matter.insert("props".into(), props);
+        let attrs = attrs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    ExpandedName::new("", *name),
+                    Attribute {
+                        prefix: None,
+                        value: value.to_string(),
+                    },
+                )
+            })
+            .collect();
+        resolve(&template, "my-card", &attrs).map(Value::Object)
+    }
+
+    #[test]
+    fn resolve_converts_attributes() {
+        let props = json!({
+            "title": { "type": "string", "required": true },
+            "count": "number",
+            "featured": "bool",
+            "tags": "list",
+        });
+        let attrs = [
+            ("title", "Hi"),
+            ("count", "3"),
+            ("featured", ""),
+            ("tags", "a, b"),
+            ("class", "undeclared"),
+        ];
+        assert_eq!(
+            resolve_with(props, &attrs).unwrap(),
+            json!({ "title": "Hi", "count": 3.0, "featured": true, "tags": ["a", "b"] })
+        );
+    }
+
+    #[test]
+    fn resolve_defaults() {
+        let props = json!({
+            "title": "string",
+            "count": { "type": "number", "default": 1 },
+            "featured": "bool",
+            "tags": "list",
+        });
+        assert_eq!(
+            resolve_with(props, &[]).unwrap(),
+            json!({ "title": null, "count": 1, "featured": false, "tags": [] })
+        );
+    }
+
+    #[test]
+    fn resolve_missing_required() {
+        let props = json!({ "title": { "required": true, "default": "unused" } });
+        let error = resolve_with(props, &[]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "<my-card> is missing its required prop `title`"
+        );
+    }
+
+    #[test]
+    fn resolve_bad_number() {
+        let error = resolve_with(json!({ "count": "number" }), &[("count", "lots")]).unwrap_err();
+        assert!(matches!(
+            error,
+            PropError::Invalid(_, prop, PropType::Number, value) if prop == "count" && value == "lots"
+        ));
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse(" 2.5 ", PropType::Number), Some(json!(2.5)));
+        assert_eq!(parse("two", PropType::Number), None);
+    }
+
+    #[test]
+    fn bools() {
+        assert_eq!(parse("", PropType::Bool), Some(json!(true)));
+        assert_eq!(parse("featured", PropType::Bool), Some(json!(true)));
+        assert_eq!(parse("false", PropType::Bool), Some(json!(false)));
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(parse(r#"[1, "a"]"#, PropType::List), Some(json!([1, "a"])));
+        assert_eq!(
+            parse("news, releases", PropType::List),
+            Some(json!(["news", "releases"]))
+        );
+        assert_eq!(parse(" ", PropType::List), Some(json!([])));
+        assert_eq!(parse("{}", PropType::List), None);
+    }
+
+    #[test]
+    fn invalid_names_the_type_as_declared() {
+        let error = PropError::Invalid(
+            "my-card".to_string(),
+            "count".to_string(),
+            PropType::Number,
+            "lots".to_string(),
+        );
+        assert_eq!(
+            error.to_string(),
+            "<my-card> expects prop `count` to be a number, but got `lots`"
+        );
+    }
+}
@@ -1,164 +1,725 @@
 use notify::EventKind;
-use notify::event::AccessKind;
-use std::{env, error::Error, fs, path::Path, time::SystemTime};
+use notify::event::{AccessKind, AccessMode};
+use std::{
+    collections::{BTreeSet, HashMap},
+    convert::Infallible,
+    error::Error,
+    fmt::Display,
+    fs,
+    io::ErrorKind,
+    panic::AssertUnwindSafe,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
 
+use futures::{FutureExt, StreamExt};
+use html5ever::{QualName, local_name, ns};
 use indicatif::ProgressBar;
+use kuchikikiki::{Attribute, ExpandedName, NodeRef};
 use notify::{Event, RecursiveMode, Watcher};
+use tokio::{
+    net::TcpListener,
+    sync::{
+        broadcast::{self, Sender, error::RecvError},
+        mpsc,
+    },
+    task::LocalSet,
+};
+use warp::{
+    Filter,
+    filters::{path::Tail, sse},
+    http::StatusCode,
+};
 
 use crate::{
-    compile_template, compile_templates_recursive,
+    collections::PageIndex,
+    compile_pages,
     config::{SETTINGS, Settings},
-    copy_assets_recursive, hooks,
+    copy_asset, copy_assets_recursive, error, find_pages, hooks,
+    manifest::{Hashes, Manifest},
+    plugins::{self, Plugin},
     template::TemplateLoader,
 };
 
+/// Where the dev server serves the script that reloads pages, which is added to every page it
+/// builds.
+pub const LIVE_RELOAD_SCRIPT: &str = "/_cheetah/live-reload.js";
+
+/// Adds the script that reloads pages to every page the dev server builds.
+pub struct LiveReloadScript;
+
+impl Plugin for LiveReloadScript {
+    fn name(&self) -> &str {
+        "live-reload"
+    }
+
+    fn transform_dom(&self, dom: &NodeRef, page: &plugins::Page) -> Result<(), Box<dyn Error>> {
+        if !page.dev {
+            return Ok(());
+        }
+        let mut attrs = HashMap::new();
+        attrs.insert(
+            ExpandedName::new("", "src"),
+            Attribute {
+                prefix: None,
+                value: LIVE_RELOAD_SCRIPT.into(),
+            },
+        );
+        let script =
+            NodeRef::new_element(QualName::new(None, ns!(html), local_name!("script")), attrs);
+        // Pages without a `<body>` get it at the end of whatever they have.
+        match dom
+            .select_first("body")
+            .or_else(|_| dom.select_first("html"))
+        {
+            Ok(parent) => parent.as_node().append(script),
+            Err(_) => dom.append(script),
+        }
+        Ok(())
+    }
+}
+
+/// What open pages should do once a rebuild has finished.
+#[derive(Clone, Debug)]
+enum Reload {
+    Page,
+    /// Only the stylesheet at the given URL changed, so it can be swapped in without reloading.
+    Css(String),
+    /// The rebuild failed with the given errors, which are shown over the page.
+    Error(String),
+}
+
+impl Reload {
+    fn event(&self) -> sse::Event {
+        match self {
+            Reload::Page => sse::Event::default().event("reload").data(""),
+            Reload::Css(url) => sse::Event::default().event("css").data(url),
+            Reload::Error(errors) => sse::Event::default()
+                .event("build-error")
+                .data(error::without_colors(errors).replace('\r', "")),
+        }
+    }
+}
+
+/// How the dev server tells open pages about rebuilds.
+#[derive(Clone)]
+struct LiveReload {
+    reloads: Sender<Reload>,
+    /// The errors from the last rebuild, if it failed, for pages that are opened afterwards.
+    errors: Arc<Mutex<Option<String>>>,
+}
+
+impl LiveReload {
+    fn new() -> Self {
+        Self {
+            reloads: broadcast::channel(16).0,
+            errors: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    fn send(&self, reload: Reload) {
+        *self.errors.lock().unwrap() = match &reload {
+            Reload::Error(errors) => Some(errors.clone()),
+            _ => None,
+        };
+        // Nothing listening just means that no pages are open.
+        let _ = self.reloads.send(reload);
+    }
+
+    /// Tells open pages how to update after a rebuild. Pages showing errors from an earlier
+    /// rebuild are reloaded once one succeeds, even if it didn't change anything they show.
+    fn rebuilt(&self, reloads: Vec<Reload>) {
+        let failed_before = self.errors.lock().unwrap().is_some();
+        let failed = reloads
+            .iter()
+            .any(|reload| matches!(reload, Reload::Error(_)));
+        if failed_before && !failed {
+            self.send(Reload::Page);
+            return;
+        }
+        for reload in reloads {
+            self.send(reload);
+        }
+    }
+
+    /// Serves the live reload script, and tells the pages that load it when to reload.
+    fn filter(self) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let script = warp::path!("_cheetah" / "live-reload.js").map(|| {
+            warp::reply::with_header(
+                include_str!("live-reload.js"),
+                "content-type",
+                "text/javascript",
+            )
+        });
+        let events = warp::path!("_cheetah" / "events").map(move || {
+            // A page opened after a rebuild failed shows the errors straight away.
+            let errors = self.errors.lock().unwrap().clone().map(Reload::Error);
+            let reloads =
+                futures::stream::unfold(self.reloads.subscribe(), |mut receiver| async move {
+                    let reload = match receiver.recv().await {
+                        Ok(reload) => reload,
+                        // Missing a message only means missing which stylesheet changed.
+                        Err(RecvError::Lagged(_)) => Reload::Page,
+                        Err(RecvError::Closed) => return None,
+                    };
+                    Some((reload, receiver))
+                });
+            let events = futures::stream::iter(errors)
+                .chain(reloads)
+                .map(|reload| Ok::<_, Infallible>(reload.event()));
+            sse::reply(sse::keep_alive().stream(events))
+        });
+        script.or(events)
+    }
+}
+
 async fn compile_all(
     loader: &TemplateLoader,
+    manifest: &mut Manifest,
     progress: &ProgressBar,
-) -> Result<(), Box<dyn Error>> {
-    fs::create_dir_all("_build/pages/_scripts")?;
+) -> Result<Arc<PageIndex>, Box<dyn Error>> {
+    let (root, pages_dir, assets_dir, pages_out) = {
+        let settings = SETTINGS.lock().unwrap();
+        (
+            settings.root.clone(),
+            settings.pages_dir.clone(),
+            settings.assets_dir.clone(),
+            settings.pages_out(),
+        )
+    };
+    fs::create_dir_all(pages_out.join("_scripts"))?;
+
+    let context = hooks::Context {
+        dev: true,
+        ..Default::default()
+    };
+    hooks::run_all(progress, hooks::During::PreBuild, context).await?;
+    plugins::load_configured(&SETTINGS.lock().unwrap())?;
 
-    hooks::run_all(progress, true, None, hooks::During::PreBuild).await?;
+    progress.set_message("Indexing pages");
+    let pages = Arc::new(PageIndex::build(loader, &SETTINGS.lock().unwrap())?);
 
-    compile_templates_recursive("pages".to_string(), loader, progress)?;
+    let built = find_pages(pages_dir, loader)?;
+    compile_pages(built.clone(), None, loader, &pages, manifest, progress).await?;
 
     fs::write(
-        "_build/pages/_scripts/component.js",
+        pages_out.join("_scripts/component.js"),
         include_str!("component.js"),
     )?;
 
-    copy_assets_recursive("assets".to_string(), progress)?;
+    copy_assets_recursive(
+        assets_dir,
+        &Manifest::default(),
+        manifest,
+        &mut Hashes::new(root),
+        progress,
+    )?;
+    hooks::run_all(progress, hooks::During::PostAssets, context).await?;
 
-    hooks::run_all(progress, true, None, hooks::During::PostBuild).await?;
+    plugins::on_build_finished(&plugins::Build {
+        out: &pages_out,
+        pages: &built,
+        dev: true,
+    })?;
+    hooks::run_all(progress, hooks::During::PostBuild, context).await?;
 
-    Ok(())
+    Ok(pages)
 }
 
 pub async fn run(progress: ProgressBar) -> Result<(), Box<dyn Error>> {
     let start_time = SystemTime::now();
-    let loader = TemplateLoader::default();
+    let (loader, root) = {
+        let settings = SETTINGS.lock().unwrap();
+        (TemplateLoader::new(&settings), settings.root.clone())
+    };
 
-    compile_all(&loader, &progress).await?;
+    let live_reload = LiveReload::new();
+    let mut manifest = Manifest::default();
+    // The site is still served if it can't be built, so that the error is shown there.
+    let mut pages = match compile_all(&loader, &mut manifest, &progress).await {
+        Ok(pages) => {
+            progress.finish_with_message(format!(
+                "Initial build finished in \x1b[1m{}ms\x1b[0m",
+                SystemTime::now().duration_since(start_time)?.as_millis()
+            ));
+            pages
+        }
+        Err(e) => {
+            progress.abandon_with_message("\x1b[1;31mInitial build failed\x1b[0m");
+            let errors = report(e);
+            hooks::run_on_failure(&progress, true, &errors).await;
+            live_reload.send(Reload::Error(errors));
+            Arc::new(PageIndex::build(&loader, &SETTINGS.lock().unwrap()).unwrap_or_default())
+        }
+    };
+
+    let context = hooks::Context {
+        dev: true,
+        ..Default::default()
+    };
+    hooks::run_all(&progress, hooks::During::PreServe, context).await?;
 
-    progress.finish_with_message(format!(
-        "Initial build finished in \x1b[1m{}ms\x1b[0m",
-        SystemTime::now().duration_since(start_time)?.as_millis()
-    ));
     println!("Starting watcher.");
 
+    let reloads = live_reload.clone();
+
+    let (events, mut changes) = mpsc::unbounded_channel::<Vec<PathBuf>>();
     let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| match res {
-        Ok(event) => {
-            if event.kind != EventKind::Access(AccessKind::Close(notify::event::AccessMode::Write))
-            {
-                return;
+        Ok(event) if is_change(&event.kind) => {
+            // The rebuild task only stops along with the server.
+            let _ = events.send(event.paths);
+        }
+        Ok(_) => {}
+        Err(e) => println!("watch error: {e:?}"),
+    })?;
+    watcher.watch(&root, RecursiveMode::Recursive)?;
+
+    // Rebuilds happen one at a time. Changes made during a rebuild wait in the channel, and are
+    // all rebuilt together once it's finished. Hooks can't be sent between threads, so the task
+    // runs on this one, alongside the server.
+    let tasks = LocalSet::new();
+    tasks.spawn_local(async move {
+        while let Some(paths) = changes.recv().await {
+            // Saving a file often takes several events, and editors may save several files at
+            // once, so wait for them to settle.
+            let mut changed = paths.into_iter().collect::<BTreeSet<_>>();
+            while let Ok(Some(paths)) = tokio::time::timeout(DEBOUNCE, changes.recv()).await {
+                changed.extend(paths);
             }
-            for path in event.paths {
-                if path.exists() {
-                    let recompile_start = SystemTime::now();
+            // A bug in building one page shouldn't stop the site from being rebuilt after the next
+            // change.
+            let rebuilt = AssertUnwindSafe(rebuild(changed, &mut manifest, &mut pages, &progress))
+                .catch_unwind()
+                .await;
+            match rebuilt {
+                Ok(Some(rebuilt)) => reloads.rebuilt(rebuilt),
+                Ok(None) => {}
+                Err(_) => {
+                    SETTINGS.clear_poison();
+                    progress.abandon_with_message("\x1b[1;31mRebuild failed\x1b[0m");
+                    reloads.send(Reload::Error(
+                        "The rebuild panicked; see the terminal for details.".into(),
+                    ));
+                }
+            }
+        }
+    });
 
-                    let relative_path =
-                        pathdiff::diff_paths(&path, env::current_dir().unwrap()).unwrap();
+    let listener = bind().await?;
+    println!("Starting server on http://{}.", listener.local_addr()?);
 
-                    if relative_path.starts_with("_build/") || relative_path.starts_with(".git/") {
-                        continue;
-                    }
+    tasks
+        .run_until(serve_files(listener, Some(live_reload)))
+        .await;
 
-                    let rto = tokio::runtime::Builder::new_current_thread()
-                        .enable_all()
-                        .build()
-                        .map_err(|e| {
-                            println!("Error in compilation: {e:?}");
-                        })
-                        .ok();
+    Ok(())
+}
 
-                    if rto.is_none() {
-                        return;
-                    }
+/// How long to wait for more changes before starting a rebuild.
+const DEBOUNCE: Duration = Duration::from_millis(100);
 
-                    let rt = rto.unwrap();
-                    rt.block_on(hooks::run_all(
-                        &progress,
-                        true,
-                        Some(&relative_path),
-                        hooks::During::PreBuild,
-                    ))
-                    .map_err(|e| {
-                        println!("Error in compilation: {e:?}");
-                    })
-                    .ok();
-
-                    if relative_path == Path::new("cheetah.toml") {
-                        let mut settings = SETTINGS.lock().unwrap();
-                        *settings = Settings::new().unwrap();
-                        drop(settings);
-                        rt.block_on(compile_all(&loader, &progress))
-                            .map_err(|e| {
-                                println!("Error in compilation: {e:?}");
-                            })
-                            .ok();
-                    }
-                    if relative_path.starts_with("layouts/")
-                        || relative_path.starts_with("components/")
-                    {
-                        compile_templates_recursive("pages".to_string(), &loader, &progress)
-                            .map_err(|e| {
-                                println!("Error in compilation: {e:?}");
-                            })
-                            .ok();
-                    } else if relative_path.starts_with("pages/") {
-                        compile_template(relative_path.to_path_buf(), &loader, &progress)
-                            .map_err(|e| {
-                                println!("Error in compilation: {e:?}");
-                            })
-                            .ok();
-                    } else if relative_path.starts_with("assets/") {
-                        progress.set_message(format!(
-                            "Copying asset \x1b[1m{}\x1b[0m",
-                            relative_path.to_string_lossy()
+/// Whether a file event means that the file was created, changed, or removed. Editors that save
+/// by writing a new file and renaming it over the old one only cause create and rename events.
+fn is_change(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Create(_)
+            | EventKind::Modify(_)
+            | EventKind::Remove(_)
+            | EventKind::Access(AccessKind::Close(AccessMode::Write))
+    )
+}
+
+/// Prints an error, and returns it to be shown in open pages.
+fn report(e: impl Display) -> String {
+    eprintln!("\x1b[1;31merror:\x1b[0m {e}");
+    e.to_string()
+}
+
+/// What kind of file in the site a changed path is.
+enum Change {
+    Page(PathBuf),
+    Template(PathBuf),
+    Asset(PathBuf),
+}
+
+/// Rebuilds everything affected by the files in `changed`, which have each been created,
+/// modified, or removed, and returns how open pages should be updated. Returns `None` if none of
+/// them are part of the site, so nothing was rebuilt.
+async fn rebuild(
+    changed: BTreeSet<PathBuf>,
+    manifest: &mut Manifest,
+    pages: &mut Arc<PageIndex>,
+    progress: &ProgressBar,
+) -> Option<Vec<Reload>> {
+    let start = SystemTime::now();
+    let (changed, relative, is_config) = {
+        let settings = SETTINGS.lock().unwrap();
+        let changed = changed
+            .into_iter()
+            .filter(|path| {
+                settings.within(path, &settings.out_dir).is_none()
+                    && settings.within(path, Path::new(".git")).is_none()
+            })
+            .collect::<Vec<_>>();
+        let relative = changed
+            .iter()
+            .filter_map(|path| {
+                pathdiff::diff_paths(path, std::path::absolute(&settings.root).ok()?)
+            })
+            .collect::<Vec<_>>();
+        // Plugins are loaded along with the configuration, so they're reloaded the same way.
+        let is_config = std::iter::once(settings.config_file())
+            .chain(
+                settings
+                    .plugins
+                    .iter()
+                    .map(|plugin| settings.resolve(&plugin.path)),
+            )
+            .filter_map(|path| std::path::absolute(path).ok())
+            .any(|path| changed.contains(&path));
+        (changed, relative, is_config)
+    };
+    if changed.is_empty() {
+        return None;
+    }
+
+    let mut errors = vec![];
+    let context = hooks::Context {
+        dev: true,
+        changed: Some(&relative),
+        ..Default::default()
+    };
+    if let Err(e) = hooks::run_all(progress, hooks::During::PreBuild, context).await {
+        errors.push(report(e));
+    }
+
+    // Until the configuration is fixed, the site keeps being built with what it was before.
+    if is_config {
+        match Settings::new() {
+            Ok(new_settings) => {
+                let loader = {
+                    let mut settings = SETTINGS.lock().unwrap();
+                    *settings = new_settings;
+                    TemplateLoader::new(&settings)
+                };
+                *manifest = Manifest::default();
+                match compile_all(&loader, manifest, progress).await {
+                    Ok(new_pages) => *pages = new_pages,
+                    Err(e) => errors.push(report(e)),
+                }
+            }
+            Err(e) => errors.push(report(format!("Could not reload the configuration: {e}"))),
+        }
+    }
+
+    let (loader, root, assets_dir, out_dir, changes) = {
+        let settings = SETTINGS.lock().unwrap();
+        let changes = changed
+            .iter()
+            .filter_map(|path| {
+                let change = if let Some(page) = settings.within(path, &settings.pages_dir) {
+                    Change::Page(page)
+                } else if let Some(template) = settings
+                    .within(path, &settings.layouts_dir)
+                    .or_else(|| settings.within(path, &settings.components_dir))
+                {
+                    Change::Template(template)
+                } else {
+                    Change::Asset(settings.within(path, &settings.assets_dir)?)
+                };
+                Some((change, path.exists()))
+            })
+            .collect::<Vec<_>>();
+        (
+            TemplateLoader::new(&settings),
+            settings.root.clone(),
+            settings.assets_dir.clone(),
+            settings.resolve(&settings.out_dir),
+            changes,
+        )
+    };
+
+    let mut hashes = Hashes::new(root.clone());
+    let mut to_build = BTreeSet::new();
+    let mut stylesheets = vec![];
+    let mut reload_page = is_config;
+    let mut index_changed = false;
+    let mut assets_changed = false;
+    for (change, exists) in changes {
+        let result = match change {
+            Change::Page(page) => {
+                index_changed = true;
+                reload_page = true;
+                if !exists {
+                    manifest.remove_source(&page, &out_dir)
+                } else if root.join(&page).is_dir() {
+                    find_pages(page, &loader).map(|found| to_build.extend(found))
+                } else {
+                    to_build.insert(page);
+                    Ok(())
+                }
+            }
+            Change::Template(template) => {
+                // Only the pages that were built from the layout or component need to be
+                // rebuilt.
+                reload_page = true;
+                to_build.extend(manifest.graph.dependents(&template));
+                Ok(())
+            }
+            Change::Asset(asset) if !exists || root.join(&asset).is_dir() => {
+                assets_changed = true;
+                reload_page = true;
+                manifest.remove_source(&asset, &out_dir).and_then(|_| {
+                    copy_assets_recursive(
+                        asset,
+                        &Manifest::default(),
+                        manifest,
+                        &mut hashes,
+                        progress,
+                    )
+                })
+            }
+            Change::Asset(asset) => {
+                assets_changed = true;
+                if asset.extension().is_some_and(|ext| ext == "css") {
+                    if let Ok(path) = asset.strip_prefix(&assets_dir) {
+                        stylesheets.push(format!(
+                            "/assets/{}",
+                            path.components()
+                                .map(|component| component.as_os_str().to_string_lossy())
+                                .collect::<Vec<_>>()
+                                .join("/")
                         ));
-                        fs::create_dir_all(format!(
-                            "_build/pages/{}",
-                            relative_path.parent().unwrap().to_string_lossy()
-                        ))
-                        .unwrap();
-                        fs::copy(
-                            &relative_path,
-                            format!("_build/pages/{}", relative_path.to_string_lossy()),
-                        )
-                        .unwrap();
                     }
+                } else {
+                    reload_page = true;
+                }
+                copy_asset(&asset, manifest, &mut hashes, progress)
+            }
+        };
+        if let Err(e) = result {
+            errors.push(report(e));
+        }
+    }
 
-                    rt.block_on(hooks::run_all(
-                        &progress,
-                        true,
-                        Some(&relative_path),
-                        hooks::During::PostBuild,
-                    ))
-                    .map_err(|e| {
-                        println!("Error in compilation: {e:?}");
-                    })
-                    .ok();
-
-                    progress.finish_with_message(format!(
-                        "Rebuild finished in \x1b[1m{}ms\x1b[0m",
-                        SystemTime::now()
-                            .duration_since(recompile_start)
-                            .unwrap()
-                            .as_millis()
-                    ));
+    // A change to a page's index entry means that the pages listing it have to be rebuilt too.
+    if index_changed {
+        match PageIndex::build(&loader, &SETTINGS.lock().unwrap()) {
+            Ok(new_pages) if **pages != new_pages => {
+                *pages = Arc::new(new_pages);
+                to_build.extend(manifest.graph.listing_pages());
+            }
+            Ok(_) => {}
+            Err(e) => errors.push(report(e)),
+        }
+    }
+    to_build.extend(manifest.failed.iter().cloned());
+    to_build.retain(|page| root.join(page).is_file());
+    let built = to_build.into_iter().collect::<Vec<_>>();
+    if (!built.is_empty() || index_changed)
+        && let Err(e) = compile_pages(
+            built.clone(),
+            Some(&relative),
+            &loader,
+            pages,
+            manifest,
+            progress,
+        )
+        .await
+    {
+        errors.push(report(e));
+    }
+
+    if assets_changed
+        && let Err(e) = hooks::run_all(progress, hooks::During::PostAssets, context).await
+    {
+        errors.push(report(e));
+    }
+    let pages_out = SETTINGS.lock().unwrap().pages_out();
+    let build = plugins::Build {
+        out: &pages_out,
+        pages: &built,
+        dev: true,
+    };
+    if let Err(e) = plugins::on_build_finished(&build) {
+        errors.push(report(e));
+    }
+    if let Err(e) = hooks::run_all(progress, hooks::During::PostBuild, context).await {
+        errors.push(report(e));
+    }
+
+    progress.finish_with_message(format!(
+        "Rebuild finished in \x1b[1m{}ms\x1b[0m",
+        start.elapsed().unwrap_or_default().as_millis()
+    ));
+
+    if !errors.is_empty() {
+        let errors = errors.join("\n\n");
+        hooks::run_on_failure(progress, true, &errors).await;
+        Some(vec![Reload::Error(errors)])
+    } else if reload_page {
+        Some(vec![Reload::Page])
+    } else {
+        Some(stylesheets.into_iter().map(Reload::Css).collect())
+    }
+}
+
+/// How many ports after the configured one to try before giving up.
+const PORT_ATTEMPTS: u16 = 10;
+
+/// Listens on the configured host and port, or the next free port after it.
+async fn bind() -> Result<TcpListener, Box<dyn Error>> {
+    let (host, port) = {
+        let settings = SETTINGS.lock().unwrap();
+        (settings.host.clone(), settings.port)
+    };
+    for candidate in port..=port.saturating_add(PORT_ATTEMPTS) {
+        match TcpListener::bind((host.as_str(), candidate)).await {
+            Ok(listener) => {
+                if candidate != port {
+                    println!("Port {port} is in use, so using {candidate} instead.");
                 }
+                return Ok(listener);
             }
+            Err(e) if e.kind() == ErrorKind::AddrInUse => continue,
+            Err(e) => return Err(format!("Could not listen on {host}:{candidate}: {e}").into()),
         }
-        Err(e) => println!("watch error: {e:?}"),
-    })?;
+    }
+    Err(format!(
+        "Could not listen on {host}: ports {port} to {} are all in use",
+        port.saturating_add(PORT_ATTEMPTS)
+    )
+    .into())
+}
 
-    watcher.watch(Path::new("."), RecursiveMode::Recursive)?;
+/// Resolves `/about` to `about.html`, if there is one and `enabled` is set.
+fn clean_url(
+    pages_out: PathBuf,
+    enabled: bool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(warp::path::tail())
+        .and_then(move |tail: Tail| {
+            let path = Path::new(tail.as_str());
+            let page = (enabled
+                && !tail.as_str().is_empty()
+                && path.extension().is_none()
+                && path
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_))))
+            .then(|| pages_out.join(path).with_extension("html"));
+            async move {
+                match page {
+                    Some(page) => tokio::fs::read_to_string(page)
+                        .await
+                        .map(warp::reply::html)
+                        .map_err(|_| warp::reject::not_found()),
+                    None => Err(warp::reject::not_found()),
+                }
+            }
+        })
+}
+
+/// Serves the site's own 404 page, built from `404.html` in the pages directory, for anything
+/// that isn't found.
+async fn not_found(
+    pages_out: PathBuf,
+    rejection: warp::Rejection,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if !rejection.is_not_found() {
+        return Err(rejection);
+    }
+    match tokio::fs::read_to_string(pages_out.join("404.html")).await {
+        Ok(page) => Ok(warp::reply::with_status(
+            warp::reply::html(page),
+            StatusCode::NOT_FOUND,
+        )),
+        Err(_) => Err(rejection),
+    }
+}
 
-    println!("Starting server on port 3000.");
+async fn serve_files(listener: TcpListener, live_reload: Option<LiveReload>) {
+    let (pages_out, clean_urls) = {
+        let settings = SETTINGS.lock().unwrap();
+        (settings.pages_out(), settings.clean_urls)
+    };
+    let fileserver = warp::filters::fs::dir(pages_out.clone());
+    let site = fileserver
+        .or(clean_url(pages_out.clone(), clean_urls))
+        .recover(move |rejection| not_found(pages_out.clone(), rejection));
 
-    let fileserver = warp::filters::fs::dir("_build/pages");
+    match live_reload {
+        Some(live_reload) => {
+            warp::serve(live_reload.filter().or(site))
+                .incoming(listener)
+                .run()
+                .await
+        }
+        None => warp::serve(site).incoming(listener).run().await,
+    }
+}
 
-    warp::serve(fileserver).run(([127, 0, 0, 1], 3000)).await;
+pub async fn serve(progress: &ProgressBar) -> Result<(), Box<dyn Error>> {
+    let pages_out = SETTINGS.lock().unwrap().pages_out();
+    if !pages_out.is_dir() {
+        return Err(format!(
+            "{} does not exist; run `cheetah build` first",
+            pages_out.display()
+        )
+        .into());
+    }
+
+    let listener = bind().await?;
+    progress.finish_with_message(format!(
+        "Serving \x1b[1m{}\x1b[0m on http://{}",
+        pages_out.display(),
+        listener.local_addr()?
+    ));
+
+    serve_files(listener, None).await;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn received(receiver: &mut broadcast::Receiver<Reload>) -> Vec<String> {
+        std::iter::from_fn(|| receiver.try_recv().ok())
+            .map(|reload| format!("{reload:?}"))
+            .collect()
+    }
+
+    #[test]
+    fn success_after_failure_reloads() {
+        let live_reload = LiveReload::new();
+        let mut receiver = live_reload.reloads.subscribe();
+
+        live_reload.rebuilt(vec![Reload::Error("oops".into())]);
+        assert_eq!(received(&mut receiver), [r#"Error("oops")"#]);
+        assert!(live_reload.errors.lock().unwrap().is_some());
+
+        // Fixing the error might not change anything that pages show.
+        live_reload.rebuilt(vec![]);
+        assert_eq!(received(&mut receiver), ["Page"]);
+        assert!(live_reload.errors.lock().unwrap().is_none());
+
+        live_reload.rebuilt(vec![]);
+        assert!(received(&mut receiver).is_empty());
+    }
+
+    #[test]
+    fn stylesheets_after_failure_reload() {
+        let live_reload = LiveReload::new();
+        let mut receiver = live_reload.reloads.subscribe();
+        live_reload.rebuilt(vec![Reload::Error("oops".into())]);
+        live_reload.rebuilt(vec![Reload::Css("/assets/a.css".into())]);
+        assert_eq!(received(&mut receiver), [r#"Error("oops")"#, "Page"]);
+
+        live_reload.rebuilt(vec![Reload::Css("/assets/a.css".into())]);
+        assert_eq!(received(&mut receiver), [r#"Css("/assets/a.css")"#]);
+    }
+}
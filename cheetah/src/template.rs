@@ -1,35 +1,261 @@
-use html5ever::{QualName, local_name, ns};
+use html5ever::{LocalName, QualName, local_name, ns};
 use indexmap::IndexMap;
-use kuchiki::{Attribute, ExpandedName, NodeData, NodeRef, traits::*};
+use kuchiki::{Attribute, ElementData, ExpandedName, Node, NodeData, NodeRef, traits::*};
 use kuchikikiki as kuchiki;
-use std::{cell::RefCell, collections::HashMap, error::Error, fs, ops::DerefMut, rc::Rc};
+use locrian::eval::{EvalResult, NoSuchIdentError};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    fs,
+    ops::DerefMut,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+    time::SystemTime,
+};
 
-use crate::{bindings::BindingContext, config::SETTINGS, markdown};
+use crate::{
+    bindings::{BIND_REGEX, BindingContext, BindingError, is_truthy, to_json_value},
+    collections::PageIndex,
+    config::Settings,
+    dependencies::Dependencies,
+    error::{BuildError, Location, Span, Via},
+    frontmatter::{self, FrontMatter},
+    markdown, plugins, props,
+};
 
 #[derive(Clone, Debug)]
 pub struct Template {
     pub dom: NodeRef,
-    pub is_document: bool,
     pub extends: Option<NodeRef>,
-    pub html_str: String,
+    pub front_matter: FrontMatter,
+    /// The file the template was loaded from, relative to the site root.
+    pub path: PathBuf,
+    /// The contents of the file, including front matter, for pointing errors at.
+    pub source: Arc<str>,
+    /// Where in the source the elements that errors can be about start.
+    spans: Rc<RefCell<Spans>>,
 }
 
-#[derive(Clone, Debug)]
+/// Spans of nodes, keyed by the address of each node so that looking one up doesn't depend on
+/// how many there are. The node is kept alongside its span so that the address stays its own.
+type Spans = HashMap<*const Node, (NodeRef, Span)>;
+
+fn span_key(node: &NodeRef) -> *const Node {
+    Rc::as_ptr(&node.0)
+}
+
+/// Records `span` for `node`, unless it already has one.
+fn add_span(spans: &mut Spans, node: &NodeRef, span: Span) {
+    spans
+        .entry(span_key(node))
+        .or_insert_with(|| (node.clone(), span));
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ElementRegistrar {
     pub name: String,
-    pub template: Template,
     pub connected_scripts: Vec<String>,
 }
 
 pub type Scripts = Rc<RefCell<HashMap<String, Rc<RefCell<ElementRegistrar>>>>>;
 
+/// Variables available to bindings in addition to attributes, such as front matter.
+pub type Vars = serde_json::Map<String, Value>;
+
+/// The variables in scope for a template, which can be nested inside others. Each item of a
+/// loop gets its own scope on top of the one the loop is in, rather than a copy of every
+/// variable.
+#[derive(Clone, Default)]
+pub struct Scope(Rc<ScopeLayer>);
+
+#[derive(Default)]
+struct ScopeLayer {
+    vars: Vars,
+    parent: Option<Scope>,
+}
+
+impl Scope {
+    pub fn new(vars: Vars) -> Self {
+        Self(Rc::new(ScopeLayer { vars, parent: None }))
+    }
+
+    /// A scope inside this one, whose variables take precedence over its own.
+    pub fn with(&self, vars: Vars) -> Self {
+        Self(Rc::new(ScopeLayer {
+            vars,
+            parent: Some(self.clone()),
+        }))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.0
+            .vars
+            .get(name)
+            .or_else(|| self.0.parent.as_ref()?.get(name))
+    }
+
+    /// Every variable in scope, for passing to another template.
+    pub fn to_vars(&self) -> Vars {
+        let mut vars = match &self.0.parent {
+            Some(parent) => parent.to_vars(),
+            None => Vars::new(),
+        };
+        vars.extend(self.0.vars.clone());
+        vars
+    }
+}
+
+#[derive(Debug)]
+pub struct DirectiveError {
+    directive: &'static str,
+    message: String,
+}
+
+impl DirectiveError {
+    fn new(directive: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            directive,
+            message: message.into(),
+        }
+    }
+}
+
+impl Error for DirectiveError {}
+
+impl Display for DirectiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid <{}>: {}", self.directive, self.message)
+    }
+}
+
+fn is_directive(node: &NodeRef, name: &str) -> bool {
+    node.as_element()
+        .is_some_and(|el| el.name.ns == ns!(html) && &*el.name.local == name)
+}
+
+/// The name of the slot that a node passed into a component should go into, or an empty string for
+/// the default slot.
+fn slot_name(node: &NodeRef) -> String {
+    node.as_element()
+        .and_then(|el| el.attributes.borrow().get("slot").map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// Whether a node counts as content, rather than whitespace or a comment.
+fn has_content(node: &NodeRef) -> bool {
+    match node.data() {
+        NodeData::Text(text) => !text.borrow().trim().is_empty(),
+        NodeData::Comment(_) => false,
+        _ => true,
+    }
+}
+
+/// Whether errors can be about an element, so it needs to be found in the source: components,
+/// directives, slots, and elements with bound attributes.
+fn is_located(el: &ElementData) -> bool {
+    el.name.local.contains('-')
+        || ["collection", "for", "if", "else", "slot", "extends"].contains(&&*el.name.local)
+        || el
+            .attributes
+            .borrow()
+            .map
+            .keys()
+            .any(|name| name.local.starts_with('['))
+}
+
+/// Finds the first start tag in `source` at or after `from` for an element called `name` that has
+/// all of the given attributes.
+fn find_start_tag(source: &str, from: usize, name: &str, attrs: &[String]) -> Option<Span> {
+    let mut offset = from;
+    while let Some(found) = source.get(offset..)?.find('<') {
+        let start = offset + found;
+        offset = start + 1;
+        let rest = &source[offset..];
+        let matches_name = rest
+            .get(..name.len())
+            .is_some_and(|tag| tag.eq_ignore_ascii_case(name))
+            && rest[name.len()..].starts_with(|c: char| c.is_whitespace() || c == '>' || c == '/');
+        if !matches_name {
+            continue;
+        }
+        let mut quote = None;
+        let end = rest
+            .char_indices()
+            .find(|&(_, c)| match quote {
+                Some(q) if c == q => {
+                    quote = None;
+                    false
+                }
+                Some(_) => false,
+                None if c == '"' || c == '\'' => {
+                    quote = Some(c);
+                    false
+                }
+                None => c == '>',
+            })
+            .map_or(source.len(), |(i, _)| offset + i + 1);
+        let tag = source[start..end].to_ascii_lowercase();
+        if attrs.iter().all(|attr| tag.contains(attr.as_str())) {
+            return Some(Span { start, end });
+        }
+    }
+    None
+}
+
+/// Copies a node and all of its descendants.
+pub fn deep_clone(node: &NodeRef) -> NodeRef {
+    let data = match node.data() {
+        NodeData::Element(el) => NodeData::Element(ElementData {
+            name: el.name.clone(),
+            attributes: RefCell::new(el.attributes.borrow().clone()),
+            template_contents: el.template_contents.as_ref().map(deep_clone),
+        }),
+        data => data.clone(),
+    };
+    let copy = NodeRef::new(data);
+    for child in node.children() {
+        copy.append(deep_clone(&child));
+    }
+    copy
+}
+
+/// When a template file was last modified, its size, and which plugins it was loaded with (since
+/// they can change its source).
+type FileStamp = (SystemTime, u64, u64);
+
+thread_local! {
+    /// Templates that have already been parsed, along with the stamp of the file when they were.
+    /// Nodes can't be shared between threads, so each thread has its own cache. A file that
+    /// changes (like one edited while the dev server is running) is parsed again the next time
+    /// it's loaded, without touching the templates that didn't change.
+    static TEMPLATE_CACHE: RefCell<HashMap<PathBuf, (FileStamp, Template)>> =
+        RefCell::new(HashMap::new());
+}
+
 #[derive(Clone)]
 pub struct TemplateContext {
     pub loader: TemplateLoader,
     pub contents: Option<Vec<NodeRef>>,
     pub component_name: Option<String>,
     pub attrs: IndexMap<ExpandedName, Attribute>,
+    pub vars: Scope,
+    pub pages: Arc<PageIndex>,
     pub scripts: Scripts,
+    /// Bindings that failed, which fail the build in strict mode and are warnings otherwise.
+    pub binding_errors: Rc<RefCell<Vec<BuildError>>>,
+    /// The components and layouts that the template being rendered was reached through,
+    /// innermost first.
+    pub chain: Vec<Via>,
+    /// Every layout and component used in rendering the page, including ones that couldn't be
+    /// loaded (so that the page is rebuilt once they're created).
+    pub dependencies: Rc<RefCell<Dependencies>>,
+    /// The `always_hydrate` setting, read once per page rather than for every element.
+    pub always_hydrate: bool,
 }
 
 impl Template {
@@ -55,7 +281,7 @@ impl Template {
                 vec![],
             )
         }
-        .one(html_str.clone());
+        .one(html_str);
 
         let extends = dom
             .select("extends[template]:first-child")
@@ -70,14 +296,141 @@ impl Template {
         Ok(Self {
             extends,
             dom,
-            is_document,
-            html_str,
+            front_matter: FrontMatter::new(),
+            path: PathBuf::new(),
+            source: "".into(),
+            spans: Rc::default(),
         })
     }
     pub fn from_markdown(markdown_in: String) -> Result<Self, Box<dyn Error>> {
         Self::from_html(markdown::transform(markdown_in))
     }
 
+    /// Finds the elements that errors can be about in the source, starting at `offset` (after any
+    /// front matter). This assumes they're in the same order in the source as in the document,
+    /// and leaves out any that can't be found.
+    fn locate_elements(&self, offset: usize) {
+        let mut spans = self.spans.borrow_mut();
+        let mut cursor = offset;
+        let nodes = self
+            .extends
+            .iter()
+            .chain([&self.dom])
+            .flat_map(|node| node.inclusive_descendants());
+        for node in nodes {
+            // Text is found by its first binding, so errors in bindings can be pointed at.
+            if let Some(text) = node.as_text() {
+                let text = text.borrow();
+                if let Some(binding) = BIND_REGEX.find(&text)
+                    && let Some(found) = self.source[cursor..].find(binding.as_str())
+                {
+                    let start = cursor + found;
+                    cursor = start + binding.len();
+                    add_span(&mut spans, &node, Span { start, end: cursor });
+                }
+                continue;
+            }
+            let Some(el) = node.as_element().filter(|el| is_located(el)) else {
+                continue;
+            };
+            let attrs = el
+                .attributes
+                .borrow()
+                .map
+                .keys()
+                .map(|name| name.local.to_string())
+                .collect::<Vec<_>>();
+            if let Some(span) = find_start_tag(&self.source, cursor, &el.name.local, &attrs) {
+                cursor = span.end;
+                add_span(&mut spans, &node, span);
+            }
+        }
+    }
+
+    /// Copies the template, so that the copy can be rendered without changing the original.
+    fn copy(&self) -> Template {
+        let copy = Template {
+            dom: deep_clone(&self.dom),
+            extends: self.extends.as_ref().map(deep_clone),
+            spans: Rc::default(),
+            ..self.clone()
+        };
+        let spans = self.spans.borrow();
+        let mut copy_spans = copy.spans.borrow_mut();
+        let originals = self.extends.iter().chain([&self.dom]);
+        let copies = copy.extends.iter().chain([&copy.dom]);
+        for (original, node) in originals
+            .flat_map(|node| node.inclusive_descendants())
+            .zip(copies.flat_map(|node| node.inclusive_descendants()))
+        {
+            if let Some((_, span)) = spans.get(&span_key(&original)) {
+                add_span(&mut copy_spans, &node, *span);
+            }
+        }
+        drop(copy_spans);
+        copy
+    }
+
+    /// Gives copies of nodes (like those made by `<for>`) the same spans as the originals.
+    fn copy_spans(&self, original: &NodeRef, copy: &NodeRef) {
+        let mut spans = self.spans.borrow_mut();
+        for (original, copy) in original
+            .inclusive_descendants()
+            .zip(copy.inclusive_descendants())
+        {
+            if let Some(&(_, span)) = spans.get(&span_key(&original)) {
+                add_span(&mut spans, &copy, span);
+            }
+        }
+    }
+
+    /// The template file as a whole, for errors that can't be narrowed down any further.
+    pub fn file_location(&self) -> Location {
+        Location {
+            path: self.path.clone(),
+            source: self.source.clone(),
+            span: None,
+        }
+    }
+
+    /// Where `node` (or the closest ancestor of it that could be found) is in the source.
+    pub fn location(&self, node: &NodeRef) -> Location {
+        let spans = self.spans.borrow();
+        Location {
+            span: node
+                .inclusive_ancestors()
+                .find_map(|ancestor| spans.get(&span_key(&ancestor)).map(|(_, span)| *span)),
+            ..self.file_location()
+        }
+    }
+
+    fn error_at(&self, node: &NodeRef, e: impl Into<Box<dyn Error>>) -> Box<dyn Error> {
+        BuildError::at(e, self.location(node)).into()
+    }
+
+    /// Records bindings of `node` that failed, pointing each at its expression in the source.
+    fn report(&self, node: &NodeRef, ctx: &TemplateContext, failures: Vec<BindingError>) {
+        for failure in failures {
+            let mut location = self.location(node);
+            let needle = match &failure.attr {
+                Some(attr) => format!("[{attr}]"),
+                None => format!("{{{{{}}}}}", failure.expr),
+            };
+            location.span = location.span.and_then(|span| {
+                let start = span.start + self.source[span.start..].find(&needle)?;
+                Some(Span {
+                    start,
+                    end: start + needle.len(),
+                })
+            });
+            ctx.binding_errors.borrow_mut().push(BuildError {
+                message: failure.to_string(),
+                location: Some(location),
+                chain: ctx.chain.clone(),
+            });
+        }
+    }
+
     fn expand_tree_recursive(
         &self,
         mut root: &mut NodeRef,
@@ -88,27 +441,55 @@ impl Template {
         let scripts_ref_cloned = scripts_ref.clone();
         let node = root.deref_mut();
 
-        let settings = SETTINGS.lock().unwrap();
-        if settings.always_hydrate {
-            if let Some(name) = &ctx.component_name {
-                let mut scripts = scripts_ref.borrow_mut();
+        if ctx.always_hydrate
+            && let Some(name) = &ctx.component_name
+        {
+            let mut scripts = scripts_ref.borrow_mut();
 
-                let script_name = format!("{name}.registrar.js");
-                if scripts.get(&script_name).is_none() {
-                    scripts.insert(
-                        script_name.clone(),
-                        Rc::new(RefCell::new(ElementRegistrar {
-                            name: name.to_string(),
-                            connected_scripts: vec![],
-                            template: self.clone(),
-                        })),
-                    );
+            let script_name = format!("{name}.registrar.js");
+            if scripts.get(&script_name).is_none() {
+                scripts.insert(
+                    script_name.clone(),
+                    Rc::new(RefCell::new(ElementRegistrar {
+                        name: name.to_string(),
+                        connected_scripts: vec![],
+                    })),
+                );
+            }
+        }
+
+        if let NodeData::Element(el) = node.data()
+            && el.name.ns == ns!(html)
+        {
+            match &*el.name.local {
+                "collection" => {
+                    return self
+                        .expand_collection(node, scripts_ref, registrar, ctx)
+                        .map_err(|e| self.error_at(node, e));
+                }
+                "for" => {
+                    return self
+                        .expand_for(node, scripts_ref, registrar, ctx)
+                        .map_err(|e| self.error_at(node, e));
+                }
+                "if" => {
+                    return self
+                        .expand_if(node, scripts_ref, registrar, ctx)
+                        .map_err(|e| self.error_at(node, e));
                 }
+                "else" => {
+                    let e = DirectiveError::new("else", "must directly follow an <if>");
+                    return Err(self.error_at(node, e));
+                }
+                _ => (),
             }
         }
-        drop(settings);
 
-        for mut child in node.children() {
+        for mut child in node.children().collect::<Vec<_>>() {
+            // Directives can remove their siblings (like an `<if>` removing its `<else>`).
+            if child.parent().is_none() {
+                continue;
+            }
             let registrar = if let NodeData::Element(el) = child.data() {
                 let mut scripts = scripts_ref.borrow_mut();
                 if el.name.ns == ns!(html)
@@ -123,7 +504,6 @@ impl Template {
                                 Rc::new(RefCell::new(ElementRegistrar {
                                     name: name.to_string(),
                                     connected_scripts: vec![],
-                                    template: self.clone(),
                                 })),
                             );
                         }
@@ -147,23 +527,41 @@ impl Template {
         let binding = BindingContext::new(ctx.component_name.clone(), node.data(), ctx);
         match node.data() {
             NodeData::Element(el) => {
-                binding.expand_attributes();
+                self.report(node, ctx, binding.expand_attributes());
 
                 if el.name.local.to_string().contains('-') {
                     let contents = node.children().collect::<Vec<_>>();
                     for ele in node.children() {
                         ele.detach();
                     }
-                    let (rendered_contents, new_scripts) = ctx
+                    let via = || Via::Component(el.name.local.to_string(), self.location(node));
+                    let component_path = ctx.loader.component_path(&el.name.local);
+                    ctx.dependencies
+                        .borrow_mut()
+                        .templates
+                        .insert(component_path.clone());
+                    let component = ctx
                         .loader
-                        .load(&("components/".to_string() + &el.name.local + ".html"))?
+                        .load(component_path)
+                        .map_err(|e| BuildError::within(e, via()))?;
+                    let attrs = el.attributes.borrow().map.clone();
+                    let vars = props::resolve(&component, &el.name.local, &attrs)
+                        .map_err(|e| self.error_at(node, e))?;
+                    let (rendered_contents, new_scripts) = component
                         .render(&TemplateContext {
                             loader: ctx.loader.clone(),
                             contents: Some(contents),
-                            attrs: el.attributes.borrow().map.clone(),
+                            attrs,
+                            vars: Scope::new(vars),
+                            pages: ctx.pages.clone(),
                             component_name: Some(el.name.local.to_string()),
                             scripts: scripts_ref.clone(),
-                        })?;
+                            binding_errors: ctx.binding_errors.clone(),
+                            chain: [via()].into_iter().chain(ctx.chain.clone()).collect(),
+                            dependencies: ctx.dependencies.clone(),
+                            always_hydrate: ctx.always_hydrate,
+                        })
+                        .map_err(|e| BuildError::within(e, via()))?;
                     let mut scripts = scripts_ref_cloned.borrow_mut();
                     for (name, contents) in new_scripts {
                         scripts.insert(name.to_string(), Rc::new(RefCell::new(contents)));
@@ -177,16 +575,30 @@ impl Template {
                         },
                     );
                     node.append(rendered_contents);
-                } else if el.name.ns == ns!(html) && el.name.local == *"slot" {
-                    if let Some(contents) = &ctx.contents {
-                        for elem in contents {
+                } else if el.name.ns == ns!(html)
+                    && el.name.local == *"slot"
+                    && let Some(contents) = &ctx.contents
+                {
+                    let name = el.attributes.borrow().get("name").unwrap_or("").to_string();
+                    // Like in the shadow DOM, content goes to the first slot with a matching name,
+                    // and is left out entirely if there isn't one.
+                    let assigned = contents
+                        .iter()
+                        .filter(|elem| elem.parent().is_none() && slot_name(elem) == name)
+                        .collect::<Vec<_>>();
+                    // Whatever is inside the slot is only rendered if nothing is put into it.
+                    if assigned.iter().any(|elem| has_content(elem)) {
+                        for fallback in node.children() {
+                            fallback.detach();
+                        }
+                        for elem in assigned {
                             node.append(elem.clone());
                         }
                     }
                 }
             }
             NodeData::Text(text_ref) => {
-                binding.expand_text();
+                self.report(node, ctx, binding.expand_text());
 
                 if let Some(registrar) = registrar {
                     registrar
@@ -201,6 +613,152 @@ impl Template {
         Ok(())
     }
 
+    /// Expands `<collection dir="..." as="..." sort="..." order="..." limit="...">` into a copy
+    /// of its children for each page in `dir`, with the page bound to the name given by `as`.
+    fn expand_collection(
+        &self,
+        node: &NodeRef,
+        scripts_ref: &Scripts,
+        registrar: Option<Rc<RefCell<ElementRegistrar>>>,
+        ctx: &TemplateContext,
+    ) -> Result<(), Box<dyn Error>> {
+        let binding = BindingContext::new(ctx.component_name.clone(), node.data(), ctx);
+        self.report(node, ctx, binding.expand_attributes());
+        let attrs = node.as_element().unwrap().attributes.borrow();
+        let name = attrs.get("as").unwrap_or("page");
+        let limit = attrs
+            .get("limit")
+            .and_then(|limit| limit.parse().ok())
+            .unwrap_or(usize::MAX);
+        let pages = ctx.pages.query(
+            attrs.get("dir").unwrap_or("/"),
+            attrs.get("sort").unwrap_or("url"),
+            attrs.get("order") == Some("desc"),
+        );
+
+        ctx.dependencies.borrow_mut().pages = true;
+        let items = pages.into_iter().take(limit).map(|page| page.to_value());
+        self.expand_repeated(node, name, items, scripts_ref, registrar, ctx)
+    }
+
+    /// Expands `<for each="item in expression">` into a copy of its children for each item of
+    /// the array that the expression evaluates to.
+    fn expand_for(
+        &self,
+        node: &NodeRef,
+        scripts_ref: &Scripts,
+        registrar: Option<Rc<RefCell<ElementRegistrar>>>,
+        ctx: &TemplateContext,
+    ) -> Result<(), Box<dyn Error>> {
+        let binding = BindingContext::new(ctx.component_name.clone(), node.data(), ctx);
+        self.report(node, ctx, binding.expand_attributes());
+        let attrs = node.as_element().unwrap().attributes.borrow();
+        let each = attrs
+            .get("each")
+            .ok_or_else(|| DirectiveError::new("for", "missing an `each` attribute"))?;
+        let (name, expr) = each
+            .split_once(" in ")
+            .map(|(name, expr)| (name.trim(), expr.trim()))
+            .filter(|(name, _)| !name.is_empty() && !name.contains(char::is_whitespace))
+            .ok_or_else(|| {
+                DirectiveError::new(
+                    "for",
+                    format!("`{each}` is not of the form `item in items`"),
+                )
+            })?;
+
+        let items = match binding.eval(expr) {
+            Ok(EvalResult::Array(items)) => items,
+            Ok(EvalResult::None) => vec![],
+            Ok(other) => {
+                return Err(DirectiveError::new(
+                    "for",
+                    format!("`{expr}` evaluated to {other}, which is not an array"),
+                )
+                .into());
+            }
+            Err(e) => return Err(DirectiveError::new("for", format!("`{expr}`: {e}")).into()),
+        };
+
+        let items = items.iter().map(to_json_value);
+        self.expand_repeated(node, name, items, scripts_ref, registrar, ctx)
+    }
+
+    /// Replaces `node` with a copy of its children for each item, with `$index` and the item
+    /// bound to `name`.
+    fn expand_repeated(
+        &self,
+        node: &NodeRef,
+        name: &str,
+        items: impl Iterator<Item = Value>,
+        scripts_ref: &Scripts,
+        registrar: Option<Rc<RefCell<ElementRegistrar>>>,
+        ctx: &TemplateContext,
+    ) -> Result<(), Box<dyn Error>> {
+        for (index, item) in items.enumerate() {
+            let mut vars = Vars::new();
+            vars.insert("$index".to_string(), index.into());
+            vars.insert(name.to_string(), item);
+            let item_ctx = TemplateContext {
+                vars: ctx.vars.with(vars),
+                ..ctx.clone()
+            };
+            for child in node.children() {
+                let mut copy = deep_clone(&child);
+                self.copy_spans(&child, &copy);
+                node.insert_before(copy.clone());
+                self.expand_tree_recursive(&mut copy, scripts_ref, registrar.clone(), &item_ctx)?;
+            }
+        }
+        node.detach();
+        Ok(())
+    }
+
+    /// Replaces `<if cond="expression">` with its children if the expression is truthy, or
+    /// else with the children of an `<else>` element directly following it, if there is one.
+    fn expand_if(
+        &self,
+        node: &NodeRef,
+        scripts_ref: &Scripts,
+        registrar: Option<Rc<RefCell<ElementRegistrar>>>,
+        ctx: &TemplateContext,
+    ) -> Result<(), Box<dyn Error>> {
+        let binding = BindingContext::new(ctx.component_name.clone(), node.data(), ctx);
+        self.report(node, ctx, binding.expand_attributes());
+        let attrs = node.as_element().unwrap().attributes.borrow();
+        let cond = attrs
+            .get("cond")
+            .ok_or_else(|| DirectiveError::new("if", "missing a `cond` attribute"))?;
+        // An undefined variable (like an attribute that wasn't given) counts as false.
+        let result = match binding.eval(cond) {
+            Ok(result) => result,
+            Err(e) if e.is::<NoSuchIdentError>() => EvalResult::None,
+            Err(e) => return Err(DirectiveError::new("if", format!("`{cond}`: {e}")).into()),
+        };
+
+        let else_node = node
+            .following_siblings()
+            .find(has_content)
+            .filter(|sibling| is_directive(sibling, "else"));
+
+        let chosen = if is_truthy(&result) {
+            Some(node.clone())
+        } else {
+            else_node.clone()
+        };
+        if let Some(chosen) = chosen {
+            for mut child in chosen.children().collect::<Vec<_>>() {
+                node.insert_before(child.clone());
+                self.expand_tree_recursive(&mut child, scripts_ref, registrar.clone(), ctx)?;
+            }
+        }
+        if let Some(else_node) = else_node {
+            else_node.detach();
+        }
+        node.detach();
+        Ok(())
+    }
+
     fn render_basic(
         &self,
         ctx: &TemplateContext,
@@ -245,13 +803,24 @@ impl Template {
         &self,
         ctx: &TemplateContext,
     ) -> Result<(NodeRef, HashMap<String, ElementRegistrar>), Box<dyn Error>> {
+        // Front matter passed down from a page takes precedence over the template's own.
+        let mut vars = self.front_matter.clone();
+        vars.extend(ctx.vars.to_vars());
+        let ctx = &TemplateContext {
+            vars: Scope::new(vars),
+            ..ctx.clone()
+        };
         match &self.extends {
             Some(tmpl) => {
-                BindingContext::new(ctx.component_name.clone(), tmpl.data(), ctx)
-                    .expand_attributes();
+                let binding = BindingContext::new(ctx.component_name.clone(), tmpl.data(), ctx);
+                self.report(tmpl, ctx, binding.expand_attributes());
 
                 let attrs = tmpl.as_element().unwrap().attributes.borrow();
-                let (contents, scripts) = self.render_basic(ctx)?;
+                let (root, scripts) = self.render_basic(ctx)?;
+                let contents = root.children().collect::<Vec<_>>();
+                for elem in &contents {
+                    elem.detach();
+                }
                 let new_scripts = Rc::new(RefCell::new(HashMap::new()));
                 for (name, contents) in ctx.scripts.take() {
                     new_scripts.borrow_mut().insert(name, contents);
@@ -261,57 +830,307 @@ impl Template {
                         .borrow_mut()
                         .insert(name, Rc::new(RefCell::new(contents)));
                 }
+                let path = attrs.get("template").unwrap();
+                let via = || Via::Extends(PathBuf::from(path), self.location(tmpl));
+                ctx.dependencies
+                    .borrow_mut()
+                    .templates
+                    .insert(PathBuf::from(path));
                 ctx.loader
-                    .load(&attrs.get("template").unwrap().to_string())?
+                    .load(path)
+                    .map_err(|e| BuildError::within(e, via()))?
                     .render(&TemplateContext {
                         loader: ctx.loader.clone(),
-                        contents: Some(vec![contents]),
+                        contents: Some(contents),
                         attrs: attrs.map.clone(),
+                        vars: ctx.vars.clone(),
+                        pages: ctx.pages.clone(),
                         scripts: new_scripts,
                         component_name: None,
+                        binding_errors: ctx.binding_errors.clone(),
+                        chain: [via()].into_iter().chain(ctx.chain.clone()).collect(),
+                        dependencies: ctx.dependencies.clone(),
+                        always_hydrate: ctx.always_hydrate,
                     })
+                    .map_err(|e| BuildError::within(e, via()).into())
             }
             None => self.render_basic(ctx),
         }
     }
-    pub fn render_to_string(
-        &self,
-        ctx: &TemplateContext,
-    ) -> Result<(String, HashMap<String, ElementRegistrar>), Box<dyn Error>> {
-        let render_result = self.render(ctx)?;
-        Ok((render_result.0.to_string(), render_result.1))
-    }
 }
 
 #[derive(Clone)]
 pub struct TemplateLoader {
-    pub root: String,
+    pub root: PathBuf,
+    pub components_dir: PathBuf,
+    pub layouts_dir: PathBuf,
 }
 
 impl TemplateLoader {
-    pub fn resolve(&self, path: &String) -> String {
-        format!("{}/{}", self.root, path)
-    }
-    pub fn load(&self, name: &String) -> Result<Template, Box<dyn Error>> {
-        let contents = fs::read_to_string(self.resolve(name))?;
-        Ok(
-            match name
-                .split('.')
-                .collect::<Vec<&str>>()
-                .get(1)
-                .unwrap_or(&"html")
-            {
-                &"md" => Template::from_markdown(contents)?,
-                _ => Template::from_html(contents)?,
+    pub fn new(settings: &Settings) -> Self {
+        Self {
+            root: settings.root.clone(),
+            components_dir: settings.components_dir.clone(),
+            layouts_dir: settings.layouts_dir.clone(),
+        }
+    }
+    pub fn resolve(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.root.join(path)
+    }
+    /// Loads a template, reusing the one parsed the last time if the file hasn't changed since.
+    /// Rendering changes a template, so this always gives a fresh copy.
+    pub fn load(&self, name: impl AsRef<Path>) -> Result<Template, Box<dyn Error>> {
+        let path = self.resolve(&name);
+        let stamp = fs::metadata(&path)
+            .and_then(|metadata| Ok((metadata.modified()?, metadata.len(), plugins::generation())))
+            .ok();
+        let cached = TEMPLATE_CACHE.with_borrow(|cache| {
+            cache
+                .get(&path)
+                .filter(|(cached_stamp, _)| stamp.is_some_and(|stamp| *cached_stamp == stamp))
+                .map(|(_, template)| template.copy())
+        });
+        if let Some(template) = cached {
+            return Ok(template);
+        }
+
+        let template = self.parse(name.as_ref());
+        TEMPLATE_CACHE.with_borrow_mut(|cache| match (&template, stamp) {
+            (Ok(template), Some(stamp)) => {
+                cache.insert(path, (stamp, template.copy()));
+            }
+            _ => {
+                cache.remove(&path);
+            }
+        });
+        template
+    }
+    fn parse(&self, path: &Path) -> Result<Template, Box<dyn Error>> {
+        let contents = fs::read_to_string(self.resolve(path))
+            .map_err(|e| BuildError::new(format!("Could not read {}: {e}", path.display())))?;
+        let contents = plugins::on_load(path, contents)
+            .map_err(|e| BuildError::new(format!("Could not load {}: {e}", path.display())))?;
+        let file = Location {
+            path: path.to_path_buf(),
+            source: contents.as_str().into(),
+            span: None,
+        };
+        let (front_matter, source) = frontmatter::extract(&contents).map_err(|e| {
+            let span = e.span();
+            BuildError::at(
+                e,
+                Location {
+                    span,
+                    ..file.clone()
+                },
+            )
+        })?;
+        let offset = contents.len() - source.len();
+        let mut template = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("md") => Template::from_markdown(source.to_string())?,
+            _ => Template::from_html(source.to_string())?,
+        };
+        template.path = file.path;
+        template.source = file.source;
+        if let Some(Value::String(layout)) = front_matter.get("layout") {
+            let extends = self.layout_element(layout);
+            // Errors in the layout point at the `layout` key in the front matter.
+            let span = contents[..offset].find("layout").map(|start| {
+                let end = contents[start..offset]
+                    .find('\n')
+                    .map_or(offset, |i| start + i);
+                Span { start, end }
+            });
+            if template.extends.is_some() {
+                return Err(BuildError::at(
+                    "A page can't have both a `layout` in its front matter and an <extends>",
+                    Location {
+                        span,
+                        ..template.file_location()
+                    },
+                )
+                .into());
+            }
+            if let Some(span) = span {
+                add_span(&mut template.spans.borrow_mut(), &extends, span);
+            }
+            template.extends = Some(extends);
+        }
+        template.locate_elements(offset);
+        template.front_matter = front_matter;
+        Ok(template)
+    }
+    /// Builds the `<extends>` element equivalent to a `layout` key in front matter.
+    fn layout_element(&self, layout: &str) -> NodeRef {
+        let mut path = self.layouts_dir.join(layout);
+        if path.extension().is_none() {
+            path.set_extension("html");
+        }
+        let mut attrs = HashMap::new();
+        attrs.insert(
+            ExpandedName::new("", "template"),
+            Attribute {
+                prefix: None,
+                value: path.to_string_lossy().to_string(),
             },
+        );
+        NodeRef::new_element(
+            QualName::new(None, ns!(html), LocalName::from("extends")),
+            attrs,
         )
     }
+    /// The file that the component called `name` is in, relative to the site root.
+    pub fn component_path(&self, name: &str) -> PathBuf {
+        self.components_dir.join(format!("{name}.html"))
+    }
 }
 
-impl Default for TemplateLoader {
-    fn default() -> Self {
-        Self {
-            root: ".".to_string(),
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TempDir, context};
+
+    /// Renders a fragment on its own, with nothing to load and no pages.
+    fn render(html: &str) -> Result<String, Box<dyn Error>> {
+        render_with(html, Value::Object(Vars::new()))
+    }
+
+    /// Renders a fragment with the variables in `vars`.
+    fn render_with(html: &str, vars: Value) -> Result<String, Box<dyn Error>> {
+        let template = Template::from_html(html.to_string())?;
+        let Value::Object(vars) = vars else {
+            panic!("vars must be an object");
+        };
+        let (root, _) = template.render(&TemplateContext {
+            vars: Scope::new(vars),
+            ..context(Path::new(""))
+        })?;
+        Ok(root.children().map(|child| child.to_string()).collect())
+    }
+
+    #[test]
+    fn else_directly_after_if() {
+        let html = render(r#"<div><if cond="false">A</if><else>B</else></div>"#).unwrap();
+        assert_eq!(html, "<div>B</div>");
+    }
+
+    #[test]
+    fn else_after_whitespace() {
+        let html = render("<if cond=\"true\">A</if>\n  <!-- note -->\n<else>B</else>").unwrap();
+        assert!(html.starts_with('A') && !html.contains('B'));
+    }
+
+    #[test]
+    fn else_after_text_is_not_paired() {
+        let result = render(r#"<if cond="false">C</if> text <else>D</else>"#);
+        assert!(result.is_err_and(|e| e.to_string().contains("must directly follow an <if>")));
+    }
+
+    #[test]
+    fn layout_and_extends_conflict() {
+        let dir = TempDir::new();
+        dir.write(
+            "pages/index.html",
+            "+++\nlayout = \"a.html\"\n+++\n<extends template=\"layouts/b.html\"></extends>",
+        );
+        let error = BuildError::new(
+            context(dir.path())
+                .loader
+                .load("pages/index.html")
+                .unwrap_err(),
+        );
+        assert!(error.message.contains("both a `layout`"));
+        assert_eq!(error.location.and_then(|l| l.line_col()), Some((2, 1)));
+    }
+
+    #[test]
+    fn bindings_in_markdown() {
+        let dir = TempDir::new();
+        dir.write(
+            "pages/post.md",
+            "+++\ndate = \"2024-05-06\"\n+++\nPosted in {{ formatDate(date, \"%Y\") }}.\n",
+        );
+        let ctx = context(dir.path());
+        let (root, _) = ctx
+            .loader
+            .load("pages/post.md")
+            .unwrap()
+            .render(&ctx)
+            .unwrap();
+        assert!(root.to_string().contains("<p>Posted in 2024.</p>"));
+    }
+
+    /// Renders `page` with `components/my-frame.html` as `frame`.
+    fn render_in_frame(frame: &str, page: &str) -> NodeRef {
+        let dir = TempDir::new();
+        dir.write("components/my-frame.html", frame);
+        let template = Template::from_html(page.to_string()).unwrap();
+        template.render(&context(dir.path())).unwrap().0
+    }
+
+    fn text_of(root: &NodeRef, selector: &str) -> String {
+        root.select_first(selector).unwrap().text_contents()
+    }
+
+    #[test]
+    fn slots_by_name() {
+        let root = render_in_frame(
+            r#"<header><slot name="head">Default</slot></header><main><slot></slot></main>"#,
+            r#"<my-frame><h1 slot="head">Title</h1><p>Body</p></my-frame>"#,
+        );
+        assert_eq!(text_of(&root, "header"), "Title");
+        assert_eq!(text_of(&root, "main"), "Body");
+    }
+
+    #[test]
+    fn first_matching_slot_wins() {
+        let root = render_in_frame(
+            r#"<slot name="a"></slot>|<slot name="a">Fallback</slot>|<slot name="b"></slot>"#,
+            r#"<my-frame><b slot="a">A</b><i slot="c">C</i></my-frame>"#,
+        );
+        assert_eq!(
+            text_of(&root, "my-frame"),
+            "A|Fallback|",
+            "content without a matching slot is left out"
+        );
+    }
+
+    #[test]
+    fn fallback_content_without_any_given() {
+        let frame = "<slot>Fallback</slot>";
+        let root = render_in_frame(frame, "<my-frame>\n  <!-- nothing -->\n</my-frame>");
+        assert_eq!(text_of(&root, "my-frame").trim(), "Fallback");
+        let root = render_in_frame(frame, "<my-frame> <em>Given</em> </my-frame>");
+        assert_eq!(text_of(&root, "my-frame").trim(), "Given");
+    }
+
+    #[test]
+    fn for_binds_each_item_and_index() {
+        let html = render_with(
+            r#"<for each="tag in tags">{{ $index }}:{{ tag }},</for>"#,
+            serde_json::json!({ "tags": ["a", "b"] }),
+        )
+        .unwrap();
+        assert_eq!(html, "0:a,1:b,");
+    }
+
+    #[test]
+    fn nested_loops_see_outer_variables() {
+        let html = render_with(
+            r#"<for each="x in xs"><for each="y in ys">{{ x }}{{ y }}{{ title }} </for></for>"#,
+            serde_json::json!({ "xs": [1, 2], "ys": ["a", "b"], "title": "!" }),
+        )
+        .unwrap();
+        assert_eq!(html, "1a! 1b! 2a! 2b! ");
+    }
+
+    #[test]
+    fn loop_variables_shadow_and_then_go_out_of_scope() {
+        let html = render_with(
+            r#"<for each="tag in tags">{{ tag }}</for>/{{ tag }}"#,
+            serde_json::json!({ "tags": ["a", "b"], "tag": "outer" }),
+        )
+        .unwrap();
+        assert_eq!(html, "ab/outer");
     }
 }
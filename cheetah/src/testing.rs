@@ -0,0 +1,89 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use config::{Config, File, FileFormat};
+use indexmap::IndexMap;
+
+use crate::{
+    config::Settings,
+    template::{Scope, TemplateContext, TemplateLoader},
+};
+
+/// A directory for a test's files, which is removed once the test is done.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "cheetah-test-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Writes a file in the directory, creating the directories it's in.
+    pub fn write(&self, path: impl AsRef<Path>, contents: impl AsRef<[u8]>) {
+        let path = self.0.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A context for rendering templates from a site at `root`, with the default directories.
+pub fn context(root: &Path) -> TemplateContext {
+    TemplateContext {
+        loader: TemplateLoader {
+            root: root.to_path_buf(),
+            components_dir: PathBuf::from("components"),
+            layouts_dir: PathBuf::from("layouts"),
+        },
+        contents: None,
+        component_name: None,
+        attrs: IndexMap::new(),
+        vars: Scope::default(),
+        pages: Arc::default(),
+        scripts: Rc::default(),
+        binding_errors: Rc::default(),
+        chain: vec![],
+        dependencies: Rc::default(),
+        always_hydrate: false,
+    }
+}
+
+/// The settings for a site at `root` without a `cheetah.toml`, as `cheetah build` would use.
+pub fn settings(root: &Path) -> Settings {
+    Config::builder()
+        .set_default("strict", true)
+        .unwrap()
+        .add_source(File::from_str(
+            include_str!("config.default.toml"),
+            FileFormat::Toml,
+        ))
+        .set_override("root", root.to_string_lossy().to_string())
+        .unwrap()
+        .build()
+        .unwrap()
+        .try_deserialize()
+        .unwrap()
+}
@@ -0,0 +1,398 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use html5ever::{QualName, local_name, ns};
+use kuchiki::{NodeRef, Selectors, traits::*};
+use kuchikikiki as kuchiki;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use wasmi::{AsContextMut, Caller, Config, Engine, Extern, Instance, Linker, Module, Store};
+
+use crate::plugins::{Build, Page, Plugin};
+
+/// A WebAssembly plugin in `cheetah.toml`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PluginConfig {
+    /// The `.wasm` file, relative to the site root.
+    pub path: PathBuf,
+    /// Selectors for the elements on each page that are passed to the plugin to transform.
+    #[serde(default)]
+    select: Vec<String>,
+    /// The directories that the plugin can read files from, relative to the site root. It can't
+    /// touch anything else.
+    #[serde(default)]
+    read: Vec<PathBuf>,
+    /// How many instructions (roughly) each call into the plugin can run before it's stopped.
+    #[serde(default)]
+    fuel: Option<u64>,
+}
+
+/// Enough for any plugin that isn't stuck in a loop.
+const DEFAULT_FUEL: u64 = 10_000_000_000;
+
+/// What the functions that a plugin imports from cheetah can reach.
+struct HostState {
+    name: String,
+    root: PathBuf,
+    read: Vec<PathBuf>,
+    /// What the plugin said went wrong, if it called `fail`.
+    error: Option<String>,
+}
+
+/// A plugin compiled to WebAssembly, which is run without any access to the system besides the
+/// functions cheetah gives it.
+///
+/// Plugins export their memory, an `alloc(len) -> ptr` function that cheetah copies its input
+/// into, and any of `on_load`, `transform_fragment`, `on_page_written` and `on_build_finished`,
+/// along with functions for bindings named `binding_<name>`. Those take the pointer and length of
+/// their input and return a pointer and length packed into an `i64` (the pointer in the high 32
+/// bits), or 0 to leave things as they are.
+pub struct WasmPlugin {
+    name: String,
+    root: PathBuf,
+    config: PluginConfig,
+    engine: Engine,
+    module: Module,
+    linker: Linker<HostState>,
+    selectors: Vec<Selectors>,
+    /// The names of the functions it adds to bindings.
+    functions: Vec<String>,
+}
+
+/// What the exports of functions for bindings start with.
+const FUNCTION_PREFIX: &str = "binding_";
+
+/// Puts a pointer and length into one value, since functions can only return one.
+fn pack(ptr: i32, len: usize) -> i64 {
+    ((ptr as u32 as i64) << 32) | len as u32 as i64
+}
+
+fn unpack(packed: i64) -> (usize, usize) {
+    ((packed as u64 >> 32) as usize, packed as u32 as usize)
+}
+
+/// Copies `bytes` into memory that the plugin allocates for them.
+fn pass(
+    mut ctx: impl AsContextMut,
+    alloc: Option<Extern>,
+    memory: Option<Extern>,
+    bytes: &[u8],
+) -> Result<i32, Box<dyn Error>> {
+    let alloc = alloc
+        .and_then(Extern::into_func)
+        .ok_or("it doesn't export alloc")?
+        .typed::<i32, i32>(&ctx)?;
+    let memory = memory
+        .and_then(Extern::into_memory)
+        .ok_or("it doesn't export its memory")?;
+    let ptr = alloc.call(&mut ctx, bytes.len() as i32)?;
+    memory
+        .write(&mut ctx, ptr as u32 as usize, bytes)
+        .map_err(wasmi::Error::from)?;
+    Ok(ptr)
+}
+
+fn read_string(
+    caller: &Caller<'_, HostState>,
+    ptr: i32,
+    len: i32,
+) -> Result<String, Box<dyn Error>> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or("it doesn't export its memory")?;
+    let mut buffer = vec![0; len as u32 as usize];
+    memory
+        .read(caller, ptr as u32 as usize, &mut buffer)
+        .map_err(wasmi::Error::from)?;
+    Ok(String::from_utf8(buffer)?)
+}
+
+/// Whether `path` is inside one of the directories in `read`, without leaving it through `..`
+/// or starting from somewhere other than the site root.
+fn can_read(read: &[PathBuf], path: &Path) -> bool {
+    let normal = |path: &Path| {
+        path.components()
+            .filter(|component| *component != Component::CurDir)
+            .collect::<PathBuf>()
+    };
+    let path_normal = normal(path);
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+        && read.iter().any(|dir| path_normal.starts_with(normal(dir)))
+}
+
+/// Reads a file for a plugin, if it's in one of the directories the plugin can read from.
+fn read_file(caller: &mut Caller<'_, HostState>, ptr: i32, len: i32) -> Option<i64> {
+    let path = PathBuf::from(read_string(caller, ptr, len).ok()?);
+    let state = caller.data();
+    if !can_read(&state.read, &path) {
+        return None;
+    }
+    let contents = fs::read(state.root.join(&path)).ok()?;
+    let alloc = caller.get_export("alloc");
+    let memory = caller.get_export("memory");
+    let ptr = pass(caller, alloc, memory, &contents).ok()?;
+    Some(pack(ptr, contents.len()))
+}
+
+impl WasmPlugin {
+    pub fn load(root: &Path, config: &PluginConfig) -> Result<Self, Box<dyn Error>> {
+        let name = config
+            .path
+            .file_stem()
+            .map_or_else(
+                || config.path.to_string_lossy(),
+                |stem| stem.to_string_lossy(),
+            )
+            .to_string();
+        let bytes = fs::read(root.join(&config.path))
+            .map_err(|e| format!("Could not read plugin {}: {e}", config.path.display()))?;
+        let mut engine_config = Config::default();
+        engine_config.consume_fuel(true);
+        let engine = Engine::new(&engine_config);
+        let module = Module::new(&engine, &bytes[..])
+            .map_err(|e| format!("Could not load plugin {}: {e}", config.path.display()))?;
+        let selectors = config
+            .select
+            .iter()
+            .map(|selector| {
+                Selectors::compile(selector)
+                    .map_err(|_| format!("Invalid selector {selector} for plugin {name}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let functions = module
+            .exports()
+            .filter_map(|export| export.name().strip_prefix(FUNCTION_PREFIX))
+            .map(str::to_string)
+            .collect();
+
+        let mut linker = Linker::new(&engine);
+        linker.func_wrap(
+            "cheetah",
+            "read_file",
+            |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| {
+                read_file(&mut caller, ptr, len).unwrap_or(-1)
+            },
+        )?;
+        linker.func_wrap(
+            "cheetah",
+            "log",
+            |caller: Caller<'_, HostState>, ptr: i32, len: i32| {
+                if let Ok(message) = read_string(&caller, ptr, len) {
+                    let name = &caller.data().name;
+                    eprintln!("\x1b[2m[{name}]\x1b[0m {message}");
+                }
+            },
+        )?;
+        linker.func_wrap(
+            "cheetah",
+            "fail",
+            |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| {
+                let message = read_string(&caller, ptr, len)
+                    .unwrap_or_else(|_| "it failed without saying why".to_string());
+                caller.data_mut().error = Some(message);
+            },
+        )?;
+
+        Ok(Self {
+            name,
+            root: root.to_path_buf(),
+            config: config.clone(),
+            engine,
+            module,
+            linker,
+            selectors,
+            functions,
+        })
+    }
+
+    fn exports(&self, export: &str) -> bool {
+        self.module.exports().any(|e| e.name() == export)
+    }
+
+    /// Starts a fresh instance of the plugin, so that nothing is left over from the last page.
+    fn instantiate(&self) -> Result<Session, Box<dyn Error>> {
+        let mut store = Store::new(
+            &self.engine,
+            HostState {
+                name: self.name.clone(),
+                root: self.root.clone(),
+                read: self.config.read.clone(),
+                error: None,
+            },
+        );
+        store
+            .set_fuel(self.config.fuel.unwrap_or(DEFAULT_FUEL))
+            .map_err(wasmi::Error::from)?;
+        let instance = self
+            .linker
+            .instantiate(&mut store, &self.module)?
+            .start(&mut store)?;
+        Ok(Session {
+            store,
+            instance,
+            fuel: self.config.fuel.unwrap_or(DEFAULT_FUEL),
+        })
+    }
+
+    /// Calls `export` on a fresh instance, if the plugin has it.
+    fn call_once(&self, export: &str, input: &Value) -> Result<Option<String>, Box<dyn Error>> {
+        if !self.exports(export) {
+            return Ok(None);
+        }
+        self.instantiate()?.call(export, &input.to_string())
+    }
+}
+
+/// An instance of a plugin, for calling it more than once.
+struct Session {
+    store: Store<HostState>,
+    instance: Instance,
+    fuel: u64,
+}
+
+impl Session {
+    fn call(&mut self, export: &str, input: &str) -> Result<Option<String>, Box<dyn Error>> {
+        self.store.set_fuel(self.fuel).map_err(wasmi::Error::from)?;
+        let func = self
+            .instance
+            .get_typed_func::<(i32, i32), i64>(&self.store, export)?;
+        let alloc = self.instance.get_export(&self.store, "alloc");
+        let memory = self.instance.get_export(&self.store, "memory");
+        let ptr = pass(&mut self.store, alloc, memory, input.as_bytes())?;
+        let result = func.call(&mut self.store, (ptr, input.len() as i32))?;
+        if let Some(error) = self.store.data_mut().error.take() {
+            return Err(error.into());
+        }
+        if result == 0 {
+            return Ok(None);
+        }
+        let (ptr, len) = unpack(result);
+        let mut output = vec![0; len];
+        memory
+            .and_then(Extern::into_memory)
+            .ok_or("it doesn't export its memory")?
+            .read(&self.store, ptr, &mut output)
+            .map_err(wasmi::Error::from)?;
+        Ok(Some(String::from_utf8(output)?))
+    }
+}
+
+fn page_json(page: &Page) -> Value {
+    json!({
+        "path": page.path,
+        "output": page.output,
+        "front_matter": page.front_matter,
+        "dev": page.dev,
+    })
+}
+
+impl Plugin for WasmPlugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn on_load(&self, path: &Path, source: &str) -> Result<Option<String>, Box<dyn Error>> {
+        self.call_once("on_load", &json!({ "path": path, "source": source }))
+    }
+
+    /// Passes each element that matches one of the plugin's selectors to `transform_fragment` as
+    /// HTML, and puts whatever HTML it gives back in its place.
+    fn transform_dom(&self, dom: &NodeRef, page: &Page) -> Result<(), Box<dyn Error>> {
+        if self.selectors.is_empty() || !self.exports("transform_fragment") {
+            return Ok(());
+        }
+        let mut session = self.instantiate()?;
+        let page = page_json(page);
+        for selectors in &self.selectors {
+            let matches = selectors
+                .filter(dom.inclusive_descendants().elements())
+                .collect::<Vec<_>>();
+            for element in matches {
+                let node = element.as_node();
+                // An element inside one that was already replaced isn't on the page any more.
+                if !node.ancestors().any(|ancestor| ancestor == *dom) {
+                    continue;
+                }
+                let input = json!({ "page": page, "html": node.to_string() });
+                let Some(html) = session.call("transform_fragment", &input.to_string())? else {
+                    continue;
+                };
+                let fragment = kuchiki::parse_fragment(
+                    QualName::new(None, ns!(html), local_name!("body")),
+                    vec![],
+                )
+                .one(html);
+                let root = fragment.first_child().unwrap_or(fragment);
+                for child in root.children().collect::<Vec<_>>() {
+                    node.insert_before(child);
+                }
+                node.detach();
+            }
+        }
+        Ok(())
+    }
+
+    fn on_page_written(&self, page: &Page) -> Result<(), Box<dyn Error>> {
+        self.call_once("on_page_written", &page_json(page))?;
+        Ok(())
+    }
+
+    fn on_build_finished(&self, build: &Build) -> Result<(), Box<dyn Error>> {
+        let input = json!({ "out": build.out, "pages": build.pages, "dev": build.dev });
+        self.call_once("on_build_finished", &input)?;
+        Ok(())
+    }
+
+    fn functions(&self) -> Vec<String> {
+        self.functions.clone()
+    }
+
+    /// Functions get their arguments as a JSON array, and give back their result as JSON.
+    fn call_function(&self, name: &str, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+        let output = self.call_once(&format!("{FUNCTION_PREFIX}{name}"), &json!(args))?;
+        Ok(match output {
+            Some(output) => serde_json::from_str(&output)?,
+            None => Value::Null,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn can_read_from(read: &[&str], path: &str) -> bool {
+        let read = read.iter().map(PathBuf::from).collect::<Vec<_>>();
+        can_read(&read, Path::new(path))
+    }
+
+    #[test]
+    fn reads_inside_allowed_directories() {
+        assert!(can_read_from(&["content"], "content/a.md"));
+        assert!(can_read_from(&["content"], "./content/deep/b.md"));
+        assert!(can_read_from(&["./content", "data"], "content/a.md"));
+        assert!(can_read_from(&["data", "content"], "data/x.json"));
+    }
+
+    #[test]
+    fn rejects_paths_outside_them() {
+        assert!(!can_read_from(&[], "content/a.md"));
+        assert!(!can_read_from(&["content"], "cheetah.toml"));
+        assert!(!can_read_from(&["content"], "content-private/a.md"));
+        assert!(!can_read_from(&["content"], "contents"));
+    }
+
+    #[test]
+    fn rejects_escaping_the_directory() {
+        assert!(!can_read_from(&["content"], "content/../cheetah.toml"));
+        assert!(!can_read_from(&["content"], "content/a/../../secret"));
+        assert!(!can_read_from(&["content"], "../content/a.md"));
+        assert!(!can_read_from(&["content"], "/content/a.md"));
+        assert!(!can_read_from(&["/etc"], "/etc/passwd"));
+    }
+}